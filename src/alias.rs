@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{OptionalExtension, params};
+
+use crate::database;
+
+pub fn add(name: &str, path: &Path) -> crate::Result<()> {
+    let conn = database::connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO aliases (name, path) VALUES (?1, ?2)",
+        params![name, path.to_str().unwrap()],
+    )?;
+    Ok(())
+}
+
+/// Remove an alias and return whether it existed.
+pub fn remove(name: &str) -> crate::Result<bool> {
+    let conn = database::connect()?;
+    let count = conn.execute("DELETE FROM aliases WHERE name = ?1", params![name])?;
+    Ok(count > 0)
+}
+
+pub fn find(name: &str) -> crate::Result<Option<PathBuf>> {
+    let conn = database::connect()?;
+    let result = conn.query_row(
+        "SELECT path FROM aliases WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    );
+
+    let path: Option<String> = result.optional()?;
+    Ok(path.map(PathBuf::from))
+}
+
+pub fn list() -> crate::Result<Vec<(String, PathBuf)>> {
+    let conn = database::connect()?;
+    let mut statement = conn.prepare("SELECT name, path FROM aliases ORDER BY name")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get(0)?, PathBuf::from(row.get::<_, String>(1)?)))
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Print aliases as tab separated lines.
+pub fn print() -> crate::Result<()> {
+    for (name, path) in list()? {
+        println!("{}\t{}", name, path.display());
+    }
+
+    Ok(())
+}
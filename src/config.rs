@@ -1,10 +1,13 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::File;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// The port to host the daemon on.
     pub port: u16,
@@ -12,12 +15,39 @@ pub struct Config {
     pub volume: f32,
     /// Whether to loop the last song of the queue.
     pub loop_last: bool,
+    /// Seconds to keep the daemon alive after the queue finishes.
+    /// The daemon is kept alive until stopped when `0`.
+    pub idle_timeout_seconds: u64,
+    /// Whether to show the playing song in Discord.
+    pub discord_presence: bool,
+    /// Whether Discord shows titles and artists in their original script
+    /// instead of romanised, for songs that have both.
+    pub discord_unicode: bool,
     // Last.fm username for scrobbling.
     pub lastfm_username: String,
     // Last.fm password.
     pub lastfm_password: String,
     // Duration before scrobbling a track to Last.fm.
     pub lastfm_threshold_seconds: u64,
+    // Whether to scrobble titles and artists in their original script.
+    pub lastfm_unicode: bool,
+    /// Whether `pmu status` and `pmu history` print titles and
+    /// artists in their original script instead of romanised.
+    pub display_unicode: bool,
+    /// Directories containing music. These are indexed by `pmu scan` and
+    /// searched for songs in the history that have been moved or renamed.
+    pub library_roots: Vec<PathBuf>,
+    /// The osu! installation directory containing `osu!.db` and `collection.db`.
+    /// Defaults to the standard installation directory if it exists.
+    pub osu_directory: Option<PathBuf>,
+    /// The port to host the HTTP API on. The API is disabled when `null`.
+    pub http_port: Option<u16>,
+    /// Named players selected with `--player`. Each player overrides
+    /// options in this file, and must use a different `port`.
+    pub players: BTreeMap<String, Map<String, Value>>,
+    /// The name of the selected player.
+    #[serde(skip)]
+    pub player: Option<String>,
 }
 
 impl Default for Config {
@@ -26,13 +56,64 @@ impl Default for Config {
             port: 9999,
             volume: 0.2,
             loop_last: false,
+            idle_timeout_seconds: 600,
+            discord_presence: true,
+            discord_unicode: false,
             lastfm_username: "".into(),
             lastfm_password: "".into(),
             lastfm_threshold_seconds: 110,
+            lastfm_unicode: false,
+            display_unicode: false,
+            library_roots: Vec::new(),
+            osu_directory: None,
+            http_port: None,
+            players: BTreeMap::new(),
+            player: None,
         }
     }
 }
 
+impl Config {
+    /// Apply the overrides of a named player.
+    pub fn select_player(self, name: &str) -> crate::Result<Config> {
+        if !self.players.contains_key(name) {
+            return Err(format!("Player is not configured: {}", name).into());
+        }
+
+        self.check_ports()?;
+        let mut config = self.apply(name)?;
+        config.player = Some(name.to_string());
+        Ok(config)
+    }
+
+    fn apply(&self, name: &str) -> crate::Result<Config> {
+        let mut value = serde_json::to_value(self)?;
+        value.as_object_mut().unwrap().extend(self.players[name].clone());
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Check that no two players share a port, as each player has its own daemon.
+    fn check_ports(&self) -> crate::Result<()> {
+        let mut players = vec![("default".to_string(), self.port, self.http_port)];
+        for name in self.players.keys() {
+            let config = self.apply(name)?;
+            players.push((name.clone(), config.port, config.http_port));
+        }
+
+        let mut ports = HashMap::new();
+        for (name, port, http_port) in &players {
+            for port in std::iter::once(*port).chain(*http_port) {
+                if let Some(other) = ports.insert(port, name) {
+                    let message = format!("Players `{}` and `{}` use the same port: {}", other, name, port);
+                    return Err(message.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub fn directory() -> PathBuf {
     let dir = dirs::config_dir().unwrap();
     dir.join("pmu")
@@ -1,28 +1,15 @@
 use std::io::ErrorKind;
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::process::{Command, Stdio};
 
 use crate::{Config, Message};
-use crate::daemon::daemon;
+use crate::daemon::{daemon, Status};
 
 pub fn bootstrap(config: &Config) -> crate::Result<()> {
     let address = socket_address(config);
     let listener = TcpListener::bind(address)?;
     println!("Listening on: {}", address);
-
-    for conn in listener.incoming() {
-        let conn = conn?;
-        let message: Message = serde_json::from_reader(conn)?;
-        println!("{:?}", message);
-
-        match message {
-            Message::Stop => break,
-            Message::Play { path, .. } => return daemon(config, listener, path),
-            _ => (),
-        }
-    }
-
-    Ok(())
+    daemon(config, listener)
 }
 
 pub fn send(config: &Config, message: &Message) -> crate::Result<()> {
@@ -32,7 +19,7 @@ pub fn send(config: &Config, message: &Message) -> crate::Result<()> {
         Err(error) => match error.kind() {
             ErrorKind::ConnectionRefused => {
                 // Spawn daemon if not running.
-                spawn_daemon()?;
+                spawn_daemon(config)?;
                 loop {
                     // Wait for daemon to start.
                     match TcpStream::connect(address) {
@@ -51,9 +38,28 @@ pub fn send(config: &Config, message: &Message) -> crate::Result<()> {
     Ok(serde_json::to_writer(conn, message)?)
 }
 
-fn spawn_daemon() -> crate::Result<()> {
-    Command::new(std::env::current_exe()?)
-        .arg("daemon")
+/// Query the status of the daemon if it is running.
+pub fn query_status(config: &Config) -> crate::Result<Option<Status>> {
+    let conn = match TcpStream::connect(socket_address(config)) {
+        Ok(conn) => conn,
+        Err(error) => match error.kind() {
+            ErrorKind::ConnectionRefused => return Ok(None),
+            _ => return Err(error.into()),
+        },
+    };
+
+    serde_json::to_writer(&conn, &Message::Status)?;
+    conn.shutdown(Shutdown::Write)?;
+    Ok(Some(serde_json::from_reader(&conn)?))
+}
+
+fn spawn_daemon(config: &Config) -> crate::Result<()> {
+    let mut command = Command::new(std::env::current_exe()?);
+    if let Some(player) = &config.player {
+        command.arg("--player").arg(player);
+    }
+
+    command.arg("daemon")
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
@@ -1,20 +1,27 @@
 use std::collections::VecDeque;
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
-use std::net::TcpListener;
-use std::path::PathBuf;
-use std::sync::{Arc, mpsc};
-use std::sync::mpsc::Sender;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc, Mutex};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
 use std::thread;
 use std::time::{Duration, SystemTime};
 
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
+use rodio::{Decoder, dynamic_mixer, OutputStream, OutputStreamHandle, Sink, Source};
 use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
 use crate::daemon::discord::{clear_presence, discord_client, set_discord_presence};
-use crate::daemon::lastfm::{lastfm_client, lastfm_now_playing, try_scrobble};
-use crate::metadata::{find_metadata, Metadata};
+use crate::daemon::http::{Event, http_server, publish_event};
+use crate::daemon::lastfm::{Lastfm, lastfm_client, lastfm_now_playing, try_scrobble};
+use crate::daemon::Status;
+use crate::database;
+use crate::history::{self, Listen};
+use crate::library;
+use crate::metadata::{find_duration, find_metadata, Metadata};
+use crate::shuffle::Shuffle;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
@@ -23,9 +30,39 @@ pub enum Message {
     Play {
         path: PathBuf,
         now: bool,
+        // Whether to only play the preview of the song.
+        #[serde(default)]
+        preview: bool,
     },
     Skip,
     Next,
+    Seek {
+        seconds: f64,
+    },
+    Volume {
+        volume: f32,
+    },
+    Shuffle {
+        // The songs to shuffle, or `None` to stop shuffling.
+        query: Option<String>,
+    },
+    Status,
+}
+
+/// The number of songs kept in the queue while shuffling.
+const SHUFFLE_QUEUE: usize = 2;
+
+/// The length of previews for songs that do not specify one.
+const PREVIEW_SECONDS: f64 = 15.0;
+
+/// The length of the fades at either end of a preview.
+const PREVIEW_FADE: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone)]
+pub struct QueuedSong {
+    pub path: PathBuf,
+    // Whether to only play the preview of the song.
+    pub preview: bool,
 }
 
 pub struct CurrentSong {
@@ -40,113 +77,444 @@ pub struct CurrentSong {
     last_elapsed: Duration,
     // The timestamp when this song was last resumed.
     last_resume: SystemTime,
+    // The time spent playing this song when the player was
+    // last paused or seeked, which seeking does not change.
+    last_listened: Duration,
+    // The timestamp when this song started playing.
+    started: SystemTime,
+    // The length of the song if known.
+    duration: Option<Duration>,
+    // Whether the song was stopped before the end.
+    skipped: bool,
+    // The start and end of the preview of the song if only it is played.
+    // Previews are not scrobbled or recorded as listens.
+    preview: Option<(Duration, Duration)>,
 }
 
 impl CurrentSong {
     pub fn elapsed(&self) -> Duration {
+        if self.sink.is_paused() {
+            return self.last_elapsed;
+        }
+
         let delta = SystemTime::now().duration_since(self.last_resume);
         self.last_elapsed + delta.unwrap()
     }
+
+    /// The time spent playing this song, excluding pauses.
+    pub fn listened(&self) -> Duration {
+        if self.sink.is_paused() {
+            return self.last_listened;
+        }
+
+        let delta = SystemTime::now().duration_since(self.last_resume);
+        self.last_listened + delta.unwrap_or_default()
+    }
 }
 
-pub fn daemon(config: &Config, listener: TcpListener, path: PathBuf) -> crate::Result<()> {
-    // Play song immediately.
+pub fn daemon(config: &Config, listener: TcpListener) -> crate::Result<()> {
     let (_stream, stream_handle) = OutputStream::try_default()?;
-    let mut song = play_song(config, &stream_handle, path)?;
+    let mut volume = config.volume;
+    let mut song: Option<CurrentSong> = None;
+    let mut shuffle: Option<Shuffle> = None;
 
-    // Load deferred services.
+    // Load services.
     let queue = &mut VecDeque::new();
-    let discord = &mut discord_client();
+    let discord = &mut discord_client(config);
     let lastfm = &lastfm_client(config);
     let (tx, rx) = mpsc::channel::<Message>();
-    socket_listener(listener, tx.clone());
+    let status = Arc::new(Mutex::new(Status::new(None, queue, volume)));
+    let http = &http_server(config, tx.clone(), status.clone());
+    socket_listener(listener, tx.clone(), status.clone());
 
     let register_song = &mut |song: &CurrentSong, discord: &mut _| {
         sink_finished_listener(tx.clone(), song.sink.clone());
-        set_discord_presence(discord, song);
-        lastfm_now_playing(lastfm, song);
+        set_discord_presence(config, discord, song);
+        if song.preview.is_none() {
+            lastfm_now_playing(config, lastfm, song);
+        }
+
+        publish_event(http, &Event::Song {
+            path: song.path.clone(),
+            metadata: Box::new(song.metadata.clone()),
+        });
     };
 
-    register_song(&song, discord);
-    for message in rx {
+    let idle_timeout = Duration::from_secs(config.idle_timeout_seconds);
+    loop {
+        let message = match (&song, config.idle_timeout_seconds) {
+            (Some(_), _) | (None, 0) => rx.recv()?,
+            (None, _) => match rx.recv_timeout(idle_timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(error) => return Err(error.into()),
+            },
+        };
+
         println!("{:?}", message);
-        match message {
-            Message::Stop => break,
-            Message::Pause => match song.sink.is_paused() {
+        match (message, &mut song) {
+            (Message::Stop, song) => {
+                if let Some(song) = song {
+                    song.skipped = true;
+                }
+
+                break;
+            }
+            (Message::Pause, Some(song)) => match song.sink.is_paused() {
                 true => {
                     // Resume playback.
                     song.sink.play();
                     song.last_resume = SystemTime::now();
-                    set_discord_presence(discord, &song);
+                    set_discord_presence(config, discord, song);
+                    publish_event(http, &Event::Resumed);
                 }
                 false => {
                     // Pause playback.
-                    song.sink.pause();
                     song.last_elapsed = song.elapsed();
+                    song.last_listened = song.listened();
+                    song.sink.pause();
                     clear_presence(discord);
+                    publish_event(http, &Event::Paused);
                 }
             },
-            Message::Play { path, now } => match now {
-                false => queue.push_back(path),
-                true => {
-                    queue.clear();
-                    queue.push_back(path);
-                    tx.send(Message::Skip)?;
+            (Message::Play { path, preview, .. }, None) => {
+                // Play immediately when idle.
+                song = play_next(&stream_handle, Some(QueuedSong { path, preview }), queue, volume);
+                if let Some(song) = &song {
+                    register_song(song, discord);
                 }
-            },
-            Message::Skip => {
+            }
+            (Message::Play { path, now, preview }, Some(_)) => {
+                match now {
+                    false => queue.push_back(QueuedSong { path, preview }),
+                    true => {
+                        // Playing a song now replaces shuffled songs.
+                        shuffle = None;
+                        queue.clear();
+                        queue.push_back(QueuedSong { path, preview });
+                        tx.send(Message::Skip)?;
+                    }
+                }
+
+                let queue = queue_paths(queue);
+                publish_event(http, &Event::Queue { queue });
+            }
+            (Message::Skip, Some(song)) => {
                 // Immediately stop current song.
+                song.skipped = true;
                 song.sink.stop();
             }
-            Message::Next => {
-                let path = match (queue.pop_front(), config.loop_last) {
-                    (Some(path), _) => path,
-                    (None, true) => song.path.clone(),
-                    (None, false) => break,
+            (Message::Next, Some(current)) => {
+                // Ignore sinks replaced by seeking.
+                if !current.sink.empty() {
+                    continue;
+                }
+
+                let repeat = match queue.is_empty() && config.loop_last {
+                    true => Some(QueuedSong {
+                        path: current.path.clone(),
+                        preview: current.preview.is_some(),
+                    }),
+                    false => None,
                 };
 
                 // Play next song immediately.
-                let next = play_song(config, &stream_handle, path)?;
+                let next = play_next(&stream_handle, repeat, queue, volume);
                 let previous = std::mem::replace(&mut song, next);
-                register_song(&song, discord);
+                match &song {
+                    Some(song) => register_song(song, discord),
+                    None => {
+                        println!("Queue finished, waiting for songs.");
+                        clear_presence(discord);
+                        publish_event(http, &Event::Stopped);
+                    }
+                }
 
                 // Scrobble previous song.
-                try_scrobble(config, lastfm, &previous);
+                if let Some(previous) = &previous {
+                    finish_song(config, lastfm, previous);
+                }
+            }
+            (Message::Seek { seconds }, Some(song)) => {
+                let position = Duration::from_secs_f64(seconds.max(0.0));
+                let position = match seek_song(&stream_handle, song, position, volume) {
+                    Ok(position) => position,
+                    Err(error) => {
+                        println!("Failed to seek: {}", error);
+                        continue;
+                    }
+                };
+
+                sink_finished_listener(tx.clone(), song.sink.clone());
+                if !song.sink.is_paused() {
+                    set_discord_presence(config, discord, song);
+                }
+
+                publish_event(http, &Event::Seek { seconds: position.as_secs_f64() });
+            }
+            (Message::Volume { volume: next }, song) => {
+                volume = next.max(0.0);
+                if let Some(song) = song {
+                    song.sink.set_volume(volume);
+                }
+
+                publish_event(http, &Event::Volume { volume });
+            }
+            (Message::Shuffle { query: Some(query) }, _) => match Shuffle::new(query) {
+                Ok(next) => shuffle = Some(next),
+                Err(error) => println!("Failed to shuffle: {}", error),
+            },
+            (Message::Shuffle { query: None }, _) => shuffle = None,
+            // Ignore playback controls while idle.
+            _ => (),
+        }
+
+        if let Some(next) = &mut shuffle {
+            // Also choose a song to play now when idle.
+            let length = SHUFFLE_QUEUE + song.is_none() as usize;
+            match top_up(next, song.as_ref(), queue, length) {
+                Ok(added) => {
+                    if song.is_none() {
+                        song = play_next(&stream_handle, None, queue, volume);
+                        if let Some(song) = &song {
+                            register_song(song, discord);
+                        }
+                    }
+
+                    if added {
+                        let queue = queue_paths(queue);
+                        publish_event(http, &Event::Queue { queue });
+                    }
+                }
+                Err(error) => {
+                    println!("Failed to shuffle: {}", error);
+                    shuffle = None;
+                }
             }
         }
+
+        *status.lock().unwrap() = Status::new(song.as_ref(), queue, volume);
+    }
+
+    publish_event(http, &Event::Stopped);
+    if let Some(song) = &song {
+        finish_song(config, lastfm, song);
     }
 
-    try_scrobble(config, lastfm, &song);
     Ok(())
 }
 
+/// Play a song, or the first queued song if not given. Songs that cannot
+/// be played are skipped so that one bad file does not stop the daemon.
+fn play_next(
+    stream_handle: &OutputStreamHandle,
+    mut next: Option<QueuedSong>,
+    queue: &mut VecDeque<QueuedSong>,
+    volume: f32,
+) -> Option<CurrentSong> {
+    while let Some(queued) = next.take().or_else(|| queue.pop_front()) {
+        let path = queued.path.clone();
+        match play_song(stream_handle, queued, volume) {
+            Ok(song) => return Some(song),
+            Err(error) => println!("Failed to play {}: {}", path.display(), error),
+        }
+    }
+
+    None
+}
+
 fn play_song(
-    config: &Config,
     stream_handle: &OutputStreamHandle,
-    path: PathBuf,
+    QueuedSong { path, preview }: QueuedSong,
+    volume: f32,
 ) -> crate::Result<CurrentSong> {
-    // Load audio file.
-    let file = BufReader::new(File::open(&path)?);
-    let source = Decoder::new(file)?;
+    let metadata = find_metadata(&path);
+    let duration = find_duration(&path);
 
     // Load audio sink.
-    let sink = audio_sink(config, &stream_handle)?;
-    sink.append(source);
+    let sink = audio_sink(&stream_handle, volume)?;
+    let preview = preview.then(|| preview_window(&metadata, duration));
+    let position = match preview {
+        None => {
+            sink.append(audio_source(&path)?);
+            Duration::ZERO
+        }
+        Some((start, end)) => {
+            append_window(&sink, &path, start, end)?;
+            start
+        }
+    };
 
     // Construct song.
-    let metadata = find_metadata(&path);
     Ok(CurrentSong {
         path,
         sink,
         metadata,
-        last_elapsed: Duration::ZERO,
+        last_elapsed: position,
         last_resume: SystemTime::now(),
+        last_listened: Duration::ZERO,
+        started: SystemTime::now(),
+        duration,
+        skipped: false,
+        preview,
     })
 }
 
-fn audio_sink(config: &Config, handle: &OutputStreamHandle) -> crate::Result<Arc<Sink>> {
+/// Decode a song, mixing together the stems of Clone Hero songs.
+fn audio_source(path: &Path) -> crate::Result<Box<dyn Source<Item = i16> + Send>> {
+    let stems = library::stems(path);
+    if stems.len() <= 1 {
+        return Ok(Box::new(decoder(path)?));
+    }
+
+    let mut decoders = Vec::new();
+    for stem in &stems {
+        decoders.push(decoder(stem)?);
+    }
+
+    let (channels, sample_rate) = (decoders[0].channels(), decoders[0].sample_rate());
+    let (controller, mixer) = dynamic_mixer::mixer(channels, sample_rate);
+    for decoder in decoders {
+        controller.add(decoder);
+    }
+
+    Ok(Box::new(mixer))
+}
+
+fn decoder(path: &Path) -> crate::Result<Decoder<BufReader<File>>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(Decoder::new(file)?)
+}
+
+/// Play the part of a song between two positions, fading in and out.
+fn append_window(sink: &Sink, path: &Path, start: Duration, end: Duration) -> crate::Result<()> {
+    let length = end.saturating_sub(start);
+    let fade = PREVIEW_FADE.min(length / 2);
+    let body = audio_source(path)?
+        .skip_duration(start)
+        .take_duration(length - fade)
+        .fade_in(fade);
+
+    // Fade out the end separately to keep the fade short.
+    let mut tail = audio_source(path)?
+        .skip_duration(start + length - fade)
+        .take_duration(fade);
+    tail.set_filter_fadeout();
+
+    sink.append(body);
+    sink.append(tail);
+    Ok(())
+}
+
+/// The start and end of the preview of a song.
+fn preview_window(metadata: &Metadata, duration: Option<Duration>) -> (Duration, Duration) {
+    let (start, length) = match &metadata.preview {
+        Some(preview) => (preview.start_seconds, preview.length_seconds),
+        // Start partway into songs without a preview like osu! does.
+        None => match duration {
+            Some(duration) => (duration.as_secs_f64() * 0.4, None),
+            None => (0.0, None),
+        },
+    };
+
+    let start = Duration::from_secs_f64(start.max(0.0));
+    let length = Duration::from_secs_f64(length.unwrap_or(PREVIEW_SECONDS).max(0.0));
+    (start, start + length)
+}
+
+fn queue_paths(queue: &VecDeque<QueuedSong>) -> Vec<PathBuf> {
+    queue.iter().map(|queued| queued.path.clone()).collect()
+}
+
+/// Add shuffled songs until the queue has a length and return whether any were added.
+fn top_up(
+    shuffle: &mut Shuffle,
+    song: Option<&CurrentSong>,
+    queue: &mut VecDeque<QueuedSong>,
+    length: usize,
+) -> crate::Result<bool> {
+    let mut added = false;
+    while queue.len() < length {
+        let upcoming: Vec<_> = song.map(|song| song.path.as_path()).into_iter()
+            .chain(queue.iter().map(|queued| queued.path.as_path())).collect();
+        match shuffle.choose(&upcoming)? {
+            Some(path) => queue.push_back(QueuedSong { path, preview: false }),
+            None => break,
+        }
+
+        added = true;
+    }
+
+    Ok(added)
+}
+
+/// Scrobble and record a song that has stopped playing.
+fn finish_song(config: &Config, lastfm: &Lastfm, song: &CurrentSong) {
+    if song.preview.is_some() {
+        return;
+    }
+
+    try_scrobble(config, lastfm, song);
+
+    let mut listened = song.listened();
+    if let Some(duration) = song.duration {
+        listened = listened.min(duration);
+    }
+
+    let listen = Listen {
+        start: song.started,
+        listened,
+        duration: song.duration,
+        completed: !song.skipped,
+        path: Some(&song.path),
+        metadata: &song.metadata,
+    };
+
+    let result = database::connect().and_then(|conn| history::insert_listen(&conn, &listen));
+    if let Err(error) = result {
+        println!("Failed to record listen: {}", error);
+    }
+}
+
+/// Replace the sink of a song with one starting from a position,
+/// returning the position that playback actually starts from.
+fn seek_song(
+    stream_handle: &OutputStreamHandle,
+    song: &mut CurrentSong,
+    position: Duration,
+    volume: f32,
+) -> crate::Result<Duration> {
+    // Preserve paused state.
+    let sink = audio_sink(stream_handle, volume)?;
+    if song.sink.is_paused() {
+        sink.pause();
+    }
+
+    // Keep previews within their window.
+    let position = match song.preview {
+        None => {
+            sink.append(audio_source(&song.path)?.skip_duration(position));
+            position
+        }
+        Some((start, end)) => {
+            let position = position.clamp(start, end);
+            append_window(&sink, &song.path, position, end)?;
+            position
+        }
+    };
+
+    song.last_listened = song.listened();
+    let previous = std::mem::replace(&mut song.sink, sink);
+    previous.stop();
+
+    song.last_elapsed = position;
+    song.last_resume = SystemTime::now();
+    Ok(position)
+}
+
+fn audio_sink(handle: &OutputStreamHandle, volume: f32) -> crate::Result<Arc<Sink>> {
     let sink = Arc::new(Sink::try_new(handle)?);
-    sink.set_volume(config.volume);
+    sink.set_volume(volume);
     Ok(sink)
 }
 
@@ -158,15 +526,35 @@ fn sink_finished_listener(tx: Sender<Message>, sink: Arc<Sink>) {
     });
 }
 
-fn socket_listener(listener: TcpListener, tx: Sender<Message>) {
+fn socket_listener(listener: TcpListener, tx: Sender<Message>, status: Arc<Mutex<Status>>) {
     thread::spawn(move || {
         for conn in listener.incoming() {
-            let conn = conn?;
-            let message: Message = serde_json::from_reader(conn)?;
-            tx.send(message)?;
+            // A bad connection must not stop the daemon from accepting others.
+            match read_message(conn, &status) {
+                Ok(Some(message)) => {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => println!("Failed to read message: {}", error),
+            }
         }
-
-        crate::Result::Ok(())
     });
 }
 
+/// Read the message of a connection, replying directly to status requests.
+fn read_message(conn: io::Result<TcpStream>, status: &Mutex<Status>) -> crate::Result<Option<Message>> {
+    let conn = conn?;
+    let message: Message = serde_json::from_reader(&conn)?;
+    match message {
+        Message::Status => {
+            // Reply on the same connection.
+            let status = status.lock().unwrap().current();
+            serde_json::to_writer(&conn, &status)?;
+            Ok(None)
+        }
+        message => Ok(Some(message)),
+    }
+}
+
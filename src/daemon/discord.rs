@@ -4,14 +4,20 @@ use std::time::SystemTime;
 use discord_rich_presence::{DiscordIpc, new_client};
 use discord_rich_presence::activity::{Activity, Assets, Button, Timestamps};
 
+use crate::Config;
 use crate::daemon::CurrentSong;
+use crate::metadata::Origin;
 
 const DISCORD_CLIENT_ID: &str = "927041178103332965";
 
 pub struct Discord(Option<Box<dyn DiscordIpc>>);
 
-pub fn discord_client() -> Discord {
+pub fn discord_client(config: &Config) -> Discord {
     Discord((|| -> Option<Box<dyn DiscordIpc>> {
+        if !config.discord_presence {
+            return None;
+        }
+
         let mut client = new_client(DISCORD_CLIENT_ID).ok()?;
         client.connect().ok()?;
         println!("Connected to Discord.");
@@ -19,21 +25,21 @@ pub fn discord_client() -> Discord {
     })())
 }
 
-pub fn set_discord_presence(Discord(discord): &mut Discord, song: &CurrentSong) {
+pub fn set_discord_presence(config: &Config, Discord(discord): &mut Discord, song: &CurrentSong) {
     if let Some(discord) = discord {
         let start = SystemTime::now() - song.elapsed();
         let start = start.duration_since(time::UNIX_EPOCH).unwrap();
+        let unicode = config.discord_unicode;
         let mut activity = Activity::new()
-            .details(song.metadata.artist.as_deref().unwrap_or("Unknown Artist"))
-            .state(song.metadata.title.as_deref().unwrap_or("Unknown Title"))
+            .details(song.metadata.artist(unicode).unwrap_or("Unknown Artist"))
+            .state(song.metadata.title(unicode).unwrap_or("Unknown Title"))
             .timestamps(Timestamps::new().start(start.as_secs() as i64))
             .assets(Assets::new()
                 .large_image("icon")
                 .large_text("https://pmu.techno.fish/"));
 
-        if let Some(origin) = &song.metadata.origin {
-            let button = Button::new(&origin.name, &origin.link);
-            activity = activity.buttons(vec![button]);
+        if let Some(Origin { name, link: Some(link) }) = &song.metadata.origin {
+            activity = activity.buttons(vec![Button::new(name, link)]);
         }
 
         let _ = discord.set_activity(activity);
@@ -0,0 +1,282 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc, Mutex};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tiny_http::{Header, Method, ReadWrite, Request, Response, Server, StatusCode};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::WebSocket;
+
+use crate::Config;
+use crate::daemon::{Message, Status};
+use crate::metadata::Metadata;
+
+type Socket = WebSocket<Box<dyn ReadWrite + Send>>;
+
+pub struct Http(Option<Sender<Outgoing>>);
+
+/// Work for the thread that writes to WebSocket subscribers, so that
+/// slow subscribers do not block the daemon.
+enum Outgoing {
+    Subscribe(Box<Socket>),
+    Event(String),
+}
+
+/// Playback events pushed to WebSocket subscribers.
+#[derive(Debug, Serialize)]
+pub enum Event {
+    Song {
+        path: PathBuf,
+        metadata: Box<Metadata>,
+    },
+    Paused,
+    Resumed,
+    Seek {
+        seconds: f64,
+    },
+    Volume {
+        volume: f32,
+    },
+    Queue {
+        queue: Vec<PathBuf>,
+    },
+    Stopped,
+}
+
+#[derive(Deserialize)]
+struct PlayBody {
+    path: PathBuf,
+    #[serde(default)]
+    now: bool,
+    #[serde(default)]
+    preview: bool,
+}
+
+#[derive(Deserialize)]
+struct SeekBody {
+    seconds: f64,
+}
+
+#[derive(Deserialize)]
+struct VolumeBody {
+    volume: f32,
+}
+
+pub fn http_server(config: &Config, tx: Sender<Message>, status: Arc<Mutex<Status>>) -> Http {
+    Http(config.http_port.and_then(|port| {
+        let address = format!("127.0.0.1:{}", port);
+        let server = match Server::http(&address) {
+            Ok(server) => server,
+            Err(error) => {
+                println!("Failed to start HTTP API: {}", error);
+                return None;
+            }
+        };
+
+        println!("HTTP API listening on: {}", address);
+        let (outgoing, rx) = mpsc::channel();
+        event_writer(rx);
+
+        let subscribers = outgoing.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(error) = respond(request, &tx, &status, &subscribers) {
+                    println!("HTTP error: {}", error);
+                }
+            }
+        });
+
+        Some(outgoing)
+    }))
+}
+
+pub fn publish_event(Http(http): &Http, event: &Event) {
+    if let Some(outgoing) = http {
+        let text = serde_json::to_string(event).unwrap();
+        let _ = outgoing.send(Outgoing::Event(text));
+    }
+}
+
+fn event_writer(rx: Receiver<Outgoing>) {
+    thread::spawn(move || {
+        let mut subscribers: Vec<Socket> = Vec::new();
+        for outgoing in rx {
+            match outgoing {
+                Outgoing::Subscribe(socket) => subscribers.push(*socket),
+                Outgoing::Event(text) => subscribers.retain_mut(|socket| {
+                    let message = tungstenite::Message::Text(text.clone());
+                    socket.write_message(message).is_ok()
+                }),
+            }
+        }
+    });
+}
+
+fn respond(
+    mut request: Request,
+    tx: &Sender<Message>,
+    status: &Mutex<Status>,
+    subscribers: &Sender<Outgoing>,
+) -> crate::Result<()> {
+    // Web pages can send requests to local servers.
+    if !is_local_origin(&request) {
+        return Ok(request.respond(Response::empty(403))?);
+    }
+
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    if method == Method::Post && !is_json(&request) {
+        let response = Response::from_string("Expected Content-Type: application/json.");
+        return reply(request, response.with_status_code(415));
+    }
+
+    match (method, url.as_str()) {
+        (Method::Get, "/status") => {
+            let status = status.lock().unwrap().current();
+            respond_json(request, &status)
+        }
+        (Method::Get, "/queue") => {
+            let queue = status.lock().unwrap().queue.clone();
+            respond_json(request, &queue)
+        }
+        (Method::Get, "/events") => subscribe(request, subscribers),
+        // Web pages check that they may send JSON before doing so.
+        (Method::Options, _) => {
+            let methods = Header::from_bytes("Access-Control-Allow-Methods", "GET, POST").unwrap();
+            let headers = Header::from_bytes("Access-Control-Allow-Headers", "Content-Type").unwrap();
+            reply(request, Response::empty(204).with_header(methods).with_header(headers))
+        }
+        (Method::Post, path) => match message(path, &mut request) {
+            Ok(Some(message)) => {
+                tx.send(message)?;
+                reply(request, Response::empty(204))
+            }
+            Ok(None) => reply(request, Response::empty(404)),
+            Err(error) => {
+                let response = Response::from_string(error.to_string());
+                reply(request, response.with_status_code(400))
+            }
+        },
+        _ => reply(request, Response::empty(404)),
+    }
+}
+
+/// Convert an API request into a daemon message.
+fn message(path: &str, request: &mut Request) -> crate::Result<Option<Message>> {
+    Ok(Some(match path {
+        "/play" => {
+            let PlayBody { path, now, preview } = body(request)?;
+            let path = path.canonicalize()?;
+            if !path.is_file() {
+                return Err("Path provided is not a file.".into());
+            }
+
+            Message::Play { path, now, preview }
+        }
+        "/pause" => Message::Pause,
+        "/skip" => Message::Skip,
+        "/seek" => {
+            let SeekBody { seconds } = body(request)?;
+            if !seconds.is_finite() || seconds < 0.0 {
+                return Err("Seek position must be a positive number of seconds.".into());
+            }
+
+            Message::Seek { seconds }
+        }
+        "/volume" => {
+            let VolumeBody { volume } = body(request)?;
+            if !volume.is_finite() || volume < 0.0 {
+                return Err("Volume must be a positive number.".into());
+            }
+
+            Message::Volume { volume }
+        }
+        _ => return Ok(None),
+    }))
+}
+
+fn header<'a>(request: &'a Request, field: &'static str) -> Option<&'a str> {
+    request.headers().iter()
+        .find(|header| header.field.equiv(field))
+        .map(|header| header.value.as_str())
+}
+
+/// Whether a request is not from a web page, or is from a page served by this machine.
+fn is_local_origin(request: &Request) -> bool {
+    let origin = match header(request, "Origin") {
+        Some(origin) => origin,
+        None => return true,
+    };
+
+    let host = match origin.split_once("://") {
+        Some((_, host)) => host.split('/').next().unwrap(),
+        None => return false,
+    };
+
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|char| char.is_ascii_digit()) => host,
+        _ => host,
+    };
+
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+/// Whether a request has a JSON body. Web pages cannot send
+/// JSON to other origins without the permission of the server.
+fn is_json(request: &Request) -> bool {
+    match header(request, "Content-Type") {
+        Some(content_type) => {
+            let media_type = content_type.split(';').next().unwrap().trim();
+            media_type.eq_ignore_ascii_case("application/json")
+        }
+        None => false,
+    }
+}
+
+fn body<T: DeserializeOwned>(request: &mut Request) -> crate::Result<T> {
+    Ok(serde_json::from_reader(request.as_reader())?)
+}
+
+/// Respond to a request, allowing the local web page that sent it to read the response.
+fn reply<R: Read>(request: Request, response: Response<R>) -> crate::Result<()> {
+    let response = match header(&request, "Origin") {
+        Some(origin) => {
+            let allow = Header::from_bytes("Access-Control-Allow-Origin", origin).unwrap();
+            response.with_header(allow)
+        }
+        None => response,
+    };
+
+    Ok(request.respond(response)?)
+}
+
+fn respond_json<T: Serialize>(request: Request, value: &T) -> crate::Result<()> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(serde_json::to_string(value)?);
+    reply(request, response.with_header(content_type))
+}
+
+fn subscribe(request: Request, subscribers: &Sender<Outgoing>) -> crate::Result<()> {
+    let key = header(&request, "Sec-WebSocket-Key")
+        .map(|key| derive_accept_key(key.as_bytes()));
+
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let response = Response::from_string("Expected WebSocket upgrade.");
+            return reply(request, response.with_status_code(400));
+        }
+    };
+
+    // Upgrade connection to WebSocket.
+    let accept = Header::from_bytes("Sec-WebSocket-Accept", key).unwrap();
+    let response = Response::empty(StatusCode(101)).with_header(accept);
+    let stream = request.upgrade("websocket", response);
+    let socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let _ = subscribers.send(Outgoing::Subscribe(Box::new(socket)));
+    Ok(())
+}
@@ -25,9 +25,9 @@ pub fn lastfm_client(config: &Config) -> Lastfm {
     })())
 }
 
-pub fn lastfm_now_playing(Lastfm(lastfm): &Lastfm, song: &CurrentSong) {
+pub fn lastfm_now_playing(config: &Config, Lastfm(lastfm): &Lastfm, song: &CurrentSong) {
     if let Some(lastfm) = lastfm {
-        if let Some(scrobble) = create_scrobble(song) {
+        if let Some(scrobble) = create_scrobble(config, song) {
             let _ = lastfm.now_playing(&scrobble);
         }
     }
@@ -35,17 +35,17 @@ pub fn lastfm_now_playing(Lastfm(lastfm): &Lastfm, song: &CurrentSong) {
 
 pub fn try_scrobble(config: &Config, Lastfm(lastfm): &Lastfm, song: &CurrentSong) {
     if let Some(lastfm) = lastfm {
-        if song.elapsed().as_secs() >= config.lastfm_threshold_seconds {
-            if let Some(scrobble) = create_scrobble(song) {
+        if song.listened().as_secs() >= config.lastfm_threshold_seconds {
+            if let Some(scrobble) = create_scrobble(config, song) {
                 let _ = lastfm.scrobble(&scrobble);
             }
         }
     }
 }
 
-fn create_scrobble(song: &CurrentSong) -> Option<Scrobble> {
-    let artist = song.metadata.artist.as_ref()?;
-    let title = song.metadata.title.as_ref()?;
+fn create_scrobble(config: &Config, song: &CurrentSong) -> Option<Scrobble> {
+    let artist = song.metadata.artist(config.lastfm_unicode)?;
+    let title = song.metadata.title(config.lastfm_unicode)?;
     let album = match &song.metadata.album {
         Some(album) => album.as_str(),
         None => "",
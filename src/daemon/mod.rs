@@ -1,7 +1,10 @@
 pub use client::*;
 pub use daemon::*;
+pub use status::*;
 
 mod discord;
 mod daemon;
 mod client;
 mod lastfm;
+mod http;
+mod status;
@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::{CurrentSong, QueuedSong};
+use crate::metadata::Metadata;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Status {
+    // The song currently loaded into the player.
+    // This is empty while the player is idle.
+    pub song: Option<SongStatus>,
+    // The songs waiting to be played.
+    pub queue: Vec<PathBuf>,
+    // The volume of the player.
+    pub volume: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongStatus {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    pub paused: bool,
+    // Seconds elapsed into the playback of this song.
+    pub elapsed: f64,
+    // The time this snapshot was taken.
+    #[serde(skip, default = "SystemTime::now")]
+    taken: SystemTime,
+}
+
+impl Status {
+    pub fn new(song: Option<&CurrentSong>, queue: &VecDeque<QueuedSong>, volume: f32) -> Self {
+        let song = song.map(|song| SongStatus {
+            path: song.path.clone(),
+            metadata: song.metadata.clone(),
+            paused: song.sink.is_paused(),
+            elapsed: song.elapsed().as_secs_f64(),
+            taken: SystemTime::now(),
+        });
+
+        Status {
+            song,
+            queue: queue.iter().map(|queued| queued.path.clone()).collect(),
+            volume,
+        }
+    }
+
+    /// Advance the elapsed time of this snapshot to the present.
+    pub fn current(&self) -> Status {
+        let mut status = self.clone();
+        if let Some(song) = &mut status.song {
+            if !song.paused {
+                let now = SystemTime::now();
+                let delta = now.duration_since(song.taken).unwrap_or_default();
+                song.elapsed += delta.as_secs_f64();
+                song.taken = now;
+            }
+        }
+
+        status
+    }
+}
@@ -0,0 +1,281 @@
+use std::fs;
+use std::path::PathBuf;
+
+use rusqlite::{Connection, TransactionBehavior};
+
+use crate::{config, history};
+
+/// Schema migrations in the order they are applied. The `user_version`
+/// of a database is the number of migrations applied to it. Existing
+/// migrations must never be changed; add a new migration instead.
+const MIGRATIONS: &[(&str, &str)] = &[
+    // Tables may exist in databases created before versioning.
+    ("Create history table", "
+        CREATE TABLE IF NOT EXISTS history (
+            timestamp INTEGER,
+            input TEXT,
+            path TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_history_timestamp_input
+        ON history (
+            timestamp,
+            input
+        );
+    "),
+    ("Create listens table", "
+        CREATE TABLE IF NOT EXISTS listens (
+            timestamp INTEGER,
+            listened INTEGER,
+            duration INTEGER,
+            completed INTEGER,
+            path TEXT,
+            artist TEXT,
+            title TEXT,
+            album TEXT,
+            origin_name TEXT,
+            origin_link TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_listens_timestamp
+        ON listens (timestamp);
+    "),
+    ("Add history fingerprints", "
+        ALTER TABLE history ADD COLUMN fingerprint TEXT;
+        CREATE INDEX idx_history_path ON history (path);
+    "),
+    ("Create aliases table", "
+        CREATE TABLE aliases (
+            name TEXT PRIMARY KEY,
+            path TEXT NOT NULL
+        );
+    "),
+    ("Create library table", "
+        CREATE TABLE library (
+            path TEXT PRIMARY KEY,
+            mtime INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            artist TEXT,
+            title TEXT,
+            album TEXT,
+            origin_name TEXT,
+            origin_link TEXT,
+            duration INTEGER
+        );
+    "),
+    // The search index is kept in sync with the library by triggers.
+    ("Create library search index", "
+        CREATE VIRTUAL TABLE library_search USING fts5 (
+            artist,
+            title,
+            album,
+            origin,
+            path,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        INSERT INTO library_search (rowid, artist, title, album, origin, path)
+        SELECT rowid, artist, title, album, origin_name, path FROM library;
+
+        CREATE TRIGGER library_search_insert AFTER INSERT ON library BEGIN
+            INSERT INTO library_search (rowid, artist, title, album, origin, path)
+            VALUES (new.rowid, new.artist, new.title, new.album, new.origin_name, new.path);
+        END;
+
+        CREATE TRIGGER library_search_delete AFTER DELETE ON library BEGIN
+            DELETE FROM library_search WHERE rowid = old.rowid;
+        END;
+
+        CREATE TRIGGER library_search_update AFTER UPDATE ON library BEGIN
+            UPDATE library_search SET artist = new.artist, title = new.title,
+                album = new.album, origin = new.origin_name, path = new.path
+            WHERE rowid = old.rowid;
+        END;
+    "),
+    ("Create playlists table", "
+        CREATE TABLE playlists (
+            name TEXT PRIMARY KEY,
+            query TEXT NOT NULL
+        );
+    "),
+];
+
+/// The version that adds history fingerprints, after which
+/// existing history entries are fingerprinted once.
+const VERSION_FINGERPRINTS: usize = 3;
+
+pub fn path() -> PathBuf {
+    config::directory().join("data.db")
+}
+
+/// Open the database and apply pending migrations.
+pub fn connect() -> crate::Result<Connection> {
+    let mut conn = open()?;
+    if pending(&conn)? > 0 {
+        backup(&conn)?;
+        migrate(&mut conn, false)?;
+    }
+
+    Ok(conn)
+}
+
+fn open() -> crate::Result<Connection> {
+    fs::create_dir_all(config::directory())?;
+    Ok(Connection::open(path())?)
+}
+
+fn version(conn: &Connection) -> crate::Result<usize> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    match version > MIGRATIONS.len() {
+        false => Ok(version),
+        true => Err("Database was created by a newer version of pmu.".into()),
+    }
+}
+
+/// The number of migrations not yet applied.
+fn pending(conn: &Connection) -> crate::Result<usize> {
+    Ok(MIGRATIONS.len() - version(conn)?)
+}
+
+/// Copy the database before migrating it.
+fn backup(conn: &Connection) -> crate::Result<()> {
+    if fs::metadata(path())?.len() > 0 {
+        let backup = path().with_extension(format!("db.v{}.bak", version(conn)?));
+        fs::copy(path(), &backup)?;
+        eprintln!("Backed up database to: {}", backup.display());
+    }
+
+    Ok(())
+}
+
+/// Apply pending migrations and return their names. A dry run applies
+/// each migration inside a transaction that is rolled back afterwards.
+fn migrate(conn: &mut Connection, dry_run: bool) -> crate::Result<Vec<&'static str>> {
+    // Other processes may have migrated the database since the version was
+    // checked, so the version is read again after locking the database.
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = version(&transaction)?;
+
+    let mut applied = Vec::new();
+    for (index, (name, migration)) in MIGRATIONS.iter().enumerate().skip(version) {
+        let next = index + 1;
+        transaction.execute_batch(migration)
+            .map_err(|error| format!("Migration {} ({}) failed: {}", next, name, error))?;
+        if next == VERSION_FINGERPRINTS && !dry_run {
+            history::backfill_fingerprints(&transaction)?;
+        }
+
+        transaction.pragma_update(None, "user_version", next)?;
+        applied.push(*name);
+    }
+
+    match dry_run {
+        false => transaction.commit()?,
+        true => transaction.rollback()?,
+    }
+
+    Ok(applied)
+}
+
+/// Print and apply pending migrations.
+pub fn print_migrate(dry_run: bool) -> crate::Result<()> {
+    let mut conn = open()?;
+    let version = version(&conn)?;
+    println!("Schema version: {} (latest: {})", version, MIGRATIONS.len());
+    if pending(&conn)? == 0 {
+        println!("Database is up to date.");
+        return Ok(());
+    }
+
+    if !dry_run {
+        backup(&conn)?;
+    }
+
+    let applied = migrate(&mut conn, dry_run)?;
+    for (index, name) in applied.iter().enumerate() {
+        println!("Migration {}: {}", version + index + 1, name);
+    }
+
+    match dry_run {
+        false => println!("Migrated to version {}.", MIGRATIONS.len()),
+        true => println!("Dry run succeeded, no changes were made."),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::testing::TempDirectory;
+
+    use super::*;
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    fn has_table(conn: &Connection, name: &str) -> bool {
+        let query = "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1";
+        conn.query_row(query, [name], |row| row.get::<_, usize>(0)).unwrap() > 0
+    }
+
+    #[test]
+    fn migrate_empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let applied = migrate(&mut conn, false).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        assert_eq!(pending(&conn).unwrap(), 0);
+        assert!(has_table(&conn, "library"));
+        assert!(migrate(&mut conn, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrate_unversioned() {
+        let directory = TempDirectory::new("migrate_unversioned");
+        let song = directory.join("song.mp3");
+        fs::write(&song, "song").unwrap();
+
+        // Tables created before versioning.
+        let conn = &mut Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE history (timestamp INTEGER, input TEXT, path TEXT);
+            CREATE TABLE listens (
+                timestamp INTEGER, listened INTEGER, duration INTEGER, completed INTEGER,
+                path TEXT, artist TEXT, title TEXT, album TEXT, origin_name TEXT, origin_link TEXT
+            );
+            INSERT INTO history VALUES (1, 'missing', '/missing.mp3');
+        ").unwrap();
+        conn.execute("INSERT INTO history VALUES (2, 'song', ?1)", [song.to_str().unwrap()]).unwrap();
+
+        migrate(conn, false).unwrap();
+        assert_eq!(user_version(conn), MIGRATIONS.len());
+        assert_eq!(MIGRATIONS[VERSION_FINGERPRINTS - 1].0, "Add history fingerprints");
+
+        // Songs that still exist are fingerprinted while migrating.
+        let mut statement = conn.prepare("SELECT fingerprint FROM history ORDER BY timestamp").unwrap();
+        let fingerprints: Vec<Option<String>> = statement.query_map([], |row| row.get(0)).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(fingerprints, [None, Some(format!("4:{:x}", md5::compute("song")))]);
+    }
+
+    #[test]
+    fn migrate_dry_run() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let applied = migrate(&mut conn, true).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(user_version(&conn), 0);
+        assert!(!has_table(&conn, "history"));
+    }
+
+    #[test]
+    fn migrate_newer() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        assert!(version(&conn).is_err());
+        assert!(migrate(&mut conn, false).is_err());
+        assert_eq!(user_version(&conn), MIGRATIONS.len() + 1);
+    }
+}
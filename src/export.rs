@@ -0,0 +1,337 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::ArgEnum;
+use rusqlite::{OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::database;
+use crate::history::{self, Listen};
+use crate::metadata::{Metadata, Origin};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(Debug, Clone, ArgEnum)]
+pub enum Format {
+    Json,
+    Csv,
+    // ListenBrainz listens in JSON.
+    Listenbrainz,
+    // Last.fm scrobbles in CSV with `artist,album,track,date` columns.
+    // This is the format of common Last.fm export tools.
+    Lastfm,
+}
+
+/// A row of the listens table.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub timestamp: u64,
+    pub listened: u64,
+    pub duration: Option<u64>,
+    pub completed: bool,
+    pub path: Option<String>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub origin_name: Option<String>,
+    pub origin_link: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListenBrainzListen {
+    listened_at: u64,
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    #[serde(default)]
+    additional_info: AdditionalInfo,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AdditionalInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_player: Option<String>,
+}
+
+/// Write all listens to standard output.
+pub fn export(format: &Format) -> crate::Result<()> {
+    let records = records()?;
+    let stdout = io::stdout();
+    let stdout = stdout.lock();
+    match format {
+        Format::Json => serde_json::to_writer_pretty(stdout, &records)?,
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(stdout);
+            for record in &records {
+                writer.serialize(record)?;
+            }
+
+            writer.flush()?;
+        }
+        Format::Listenbrainz => {
+            // Listens must have an artist and title.
+            let listens: Vec<_> = records.into_iter().filter_map(|record| Some(ListenBrainzListen {
+                listened_at: record.timestamp,
+                track_metadata: TrackMetadata {
+                    artist_name: record.artist?,
+                    track_name: record.title?,
+                    release_name: record.album,
+                    additional_info: AdditionalInfo {
+                        duration_ms: record.duration.map(|duration| duration * 1000),
+                        origin_url: record.origin_link,
+                        media_player: Some("pmu".to_string()),
+                    },
+                },
+            })).collect();
+            serde_json::to_writer_pretty(stdout, &listens)?;
+        }
+        Format::Lastfm => {
+            // Scrobbles must have an artist and title.
+            let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(stdout);
+            for record in &records {
+                if let (Some(artist), Some(title)) = (&record.artist, &record.title) {
+                    let album = record.album.as_deref().unwrap_or_default();
+                    let date = format_lastfm_date(record.timestamp);
+                    writer.write_record([artist, album, title, &date])?;
+                }
+            }
+
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read listens from a file, skipping listens that already exist.
+pub fn import(path: &Path, format: &Format) -> crate::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let records = match format {
+        Format::Json => json_values(reader)?,
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            reader.deserialize().collect::<Result<_, _>>()?
+        }
+        Format::Listenbrainz => json_values::<ListenBrainzListen>(reader)?
+            .into_iter().map(|listen| {
+                let metadata = listen.track_metadata;
+                let info = metadata.additional_info;
+                let duration = info.duration_ms.map(|duration| duration / 1000);
+                Record {
+                    timestamp: listen.listened_at,
+                    listened: duration.unwrap_or(0),
+                    duration,
+                    completed: true,
+                    path: None,
+                    artist: Some(metadata.artist_name),
+                    title: Some(metadata.track_name),
+                    album: metadata.release_name,
+                    origin_name: None,
+                    origin_link: info.origin_url,
+                }
+            }).collect(),
+        Format::Lastfm => lastfm_records(reader)?,
+    };
+
+    let (inserted, skipped) = insert_records(&records)?;
+    println!("Imported {} listens, skipped {} duplicates.", inserted, skipped);
+    Ok(())
+}
+
+fn records() -> crate::Result<Vec<Record>> {
+    let conn = database::connect()?;
+    let mut statement = conn.prepare(
+        "SELECT timestamp, listened, duration, completed, path,
+            artist, title, album, origin_name, origin_link
+        FROM listens
+        ORDER BY timestamp")?;
+
+    let rows = statement.query_map([], |row| Ok(Record {
+        timestamp: row.get(0)?,
+        listened: row.get(1)?,
+        duration: row.get(2)?,
+        completed: row.get(3)?,
+        path: row.get(4)?,
+        artist: row.get(5)?,
+        title: row.get(6)?,
+        album: row.get(7)?,
+        origin_name: row.get(8)?,
+        origin_link: row.get(9)?,
+    }))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Insert records that do not share a timestamp and path with an existing listen.
+/// Records without a path are compared by their artist and title instead.
+fn insert_records(records: &[Record]) -> crate::Result<(usize, usize)> {
+    let mut conn = database::connect()?;
+    let transaction = conn.transaction()?;
+    let (mut inserted, mut skipped) = (0, 0);
+    for record in records {
+        let exists = transaction.query_row(
+            "SELECT 1 FROM listens WHERE timestamp = ?1 AND (path = ?2
+                OR (?2 IS NULL AND artist IS ?3 AND title IS ?4))",
+            params![record.timestamp, record.path, record.artist, record.title],
+            |_| Ok(()),
+        ).optional()?;
+
+        if exists.is_some() {
+            skipped += 1;
+            continue;
+        }
+
+        // Origins without a name are named by their link.
+        let origin = match (record.origin_name.clone(), record.origin_link.clone()) {
+            (Some(name), link) => Some(Origin { name, link }),
+            (None, Some(link)) => Some(Origin { name: link.clone(), link: Some(link) }),
+            (None, None) => None,
+        };
+
+        let metadata = Metadata {
+            artist: record.artist.clone(),
+            title: record.title.clone(),
+            album: record.album.clone(),
+            origin,
+            ..Metadata::default()
+        };
+
+        history::insert_listen(&transaction, &Listen {
+            start: UNIX_EPOCH + Duration::from_secs(record.timestamp),
+            listened: Duration::from_secs(record.listened),
+            duration: record.duration.map(Duration::from_secs),
+            completed: record.completed,
+            path: record.path.as_deref().map(Path::new),
+            metadata: &metadata,
+        })?;
+        inserted += 1;
+    }
+
+    transaction.commit()?;
+    Ok((inserted, skipped))
+}
+
+/// Read values from either a JSON array or newline delimited JSON.
+fn json_values<T: DeserializeOwned>(reader: impl Read) -> crate::Result<Vec<T>> {
+    let mut values = Vec::new();
+    for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        match value? {
+            Value::Array(array) => values.extend(array),
+            value => values.push(value),
+        }
+    }
+
+    let values = values.into_iter().map(serde_json::from_value);
+    Ok(values.collect::<Result<_, _>>()?)
+}
+
+/// Read scrobbles with `artist,album,track,date` columns,
+/// where the date is formatted like `31 Jan 2022 13:45`.
+fn lastfm_records(reader: impl Read) -> crate::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(reader);
+    for row in reader.records() {
+        let row = row?;
+        let field = |index: usize| row.get(index)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string);
+
+        let date = row.get(3).unwrap_or_default();
+        let timestamp = parse_lastfm_date(date)
+            .ok_or_else(|| format!("Invalid Last.fm date: {}", date))?;
+
+        records.push(Record {
+            timestamp,
+            listened: 0,
+            duration: None,
+            completed: true,
+            path: None,
+            artist: field(0),
+            title: field(2),
+            album: field(1),
+            origin_name: None,
+            origin_link: None,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Parse a UTC date such as `31 Jan 2022 13:45` into a unix timestamp.
+fn parse_lastfm_date(date: &str) -> Option<u64> {
+    let mut parts = date.split_whitespace();
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|name| month.starts_with(name))? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let (hour, minute) = parts.next()?.split_once(':')?;
+    let (hour, minute): (u64, u64) = (hour.parse().ok()?, minute.parse().ok()?);
+
+    // Days since the unix epoch for a proleptic Gregorian date.
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+    Some(days * 86400 + hour * 3600 + minute * 60)
+}
+
+/// Format a unix timestamp as a UTC date such as `31 Jan 2022 13:45`.
+fn format_lastfm_date(timestamp: u64) -> String {
+    // Proleptic Gregorian date for days since the unix epoch.
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = timestamp / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+
+    let seconds = timestamp % 86400;
+    let (hour, minute) = (seconds / 3600, seconds % 3600 / 60);
+    format!("{:02} {} {} {:02}:{:02}", day, MONTHS[month as usize - 1], year, hour, minute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lastfm_date() {
+        assert_eq!(parse_lastfm_date("1 Jan 1970 00:00"), Some(0));
+        assert_eq!(parse_lastfm_date("31 Jan 2022 13:45"), Some(1643636700));
+        assert_eq!(parse_lastfm_date("29 Feb 2024 23:59"), Some(1709251140));
+        assert_eq!(parse_lastfm_date("31 Jan 2022"), None);
+        assert_eq!(parse_lastfm_date("31 Foo 2022 13:45"), None);
+    }
+
+    #[test]
+    fn lastfm_date_round_trip() {
+        for timestamp in [0, 951782400, 1643636700, 1709251140, 4102444800] {
+            let date = format_lastfm_date(timestamp);
+            assert_eq!(parse_lastfm_date(&date), Some(timestamp), "{}", date);
+        }
+
+        assert_eq!(format_lastfm_date(1643636700), "31 Jan 2022 13:45");
+    }
+}
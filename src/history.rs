@@ -1,34 +1,70 @@
-use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use regex::Regex;
 use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
 
-use crate::config;
+use crate::{database, library};
+use crate::metadata::{find_metadata, Metadata};
 
-fn connect() -> crate::Result<Connection> {
-    let directory = config::directory();
-    let path = &directory.join("data.db");
-    let conn = Connection::open(path)?;
+pub struct Filter {
+    // Maximum number of entries.
+    pub limit: Option<usize>,
+    // Only entries within this duration of the present.
+    pub since: Option<Duration>,
+    // Only entries with an input, path or metadata matching this pattern.
+    pub grep: Option<Regex>,
+    // Only the latest entry for each path.
+    pub unique: bool,
+}
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS history (
-            timestamp INTEGER,
-            input TEXT,
-            path TEXT
-        )",
-        [],
-    )?;
+/// A song played by the daemon.
+#[derive(Debug)]
+pub struct Listen<'a> {
+    // The time playback started.
+    pub start: SystemTime,
+    // The time spent playing, excluding pauses.
+    pub listened: Duration,
+    // The length of the song if known.
+    pub duration: Option<Duration>,
+    // Whether the song played until the end.
+    pub completed: bool,
+    // The played file, unknown for imported listens.
+    pub path: Option<&'a Path>,
+    pub metadata: &'a Metadata,
+}
 
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_history_timestamp_input
-        ON history (
-            timestamp,
-            input
-        )",
-        [],
-    )?;
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub timestamp: u64,
+    // The local time of the timestamp.
+    #[serde(skip)]
+    pub date: String,
+    pub input: String,
+    pub path: PathBuf,
+    pub metadata: Metadata,
+}
 
-    Ok(conn)
+/// A past song matching a fuzzy query.
+#[derive(Debug)]
+pub struct Match {
+    pub path: PathBuf,
+    // The song name recorded by past listens.
+    pub name: Option<String>,
+    pub score: i64,
+    // The last time the song was played.
+    pub timestamp: u64,
+}
+
+#[derive(Default)]
+struct Candidate {
+    fields: Vec<String>,
+    name: Option<String>,
+    timestamp: u64,
 }
 
 pub fn insert(input: &Path, path: &Path) -> crate::Result<()> {
@@ -36,16 +72,134 @@ pub fn insert(input: &Path, path: &Path) -> crate::Result<()> {
     let duration = now.duration_since(UNIX_EPOCH)?;
     let timestamp = duration.as_secs();
 
-    let conn = connect()?;
+    let fingerprint = library::fingerprint(path).ok();
+    let conn = database::connect()?;
+    conn.execute(
+        "INSERT INTO history (timestamp, input, path, fingerprint) VALUES (?1, ?2, ?3, ?4)",
+        params![timestamp, input.to_str().unwrap(), path.to_str().unwrap(), fingerprint],
+    )?;
+
+    Ok(())
+}
+
+/// Fingerprint songs that still exist from entries recorded before
+/// fingerprints were, so that they can be found if moved later.
+/// This runs once, when the database is migrated to add fingerprints.
+pub fn backfill_fingerprints(conn: &Connection) -> crate::Result<()> {
+    let mut statement = conn.prepare("SELECT DISTINCT path FROM history WHERE fingerprint IS NULL")?;
+    let paths = statement.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for path in paths {
+        if let Ok(fingerprint) = library::fingerprint(Path::new(&path)) {
+            conn.execute(
+                "UPDATE history SET fingerprint = ?1 WHERE path = ?2 AND fingerprint IS NULL",
+                params![fingerprint, path],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The latest recorded fingerprint of a path.
+pub fn find_fingerprint(path: &Path) -> crate::Result<Option<String>> {
+    let conn = database::connect()?;
+    let result = conn.query_row(
+        "SELECT fingerprint FROM history
+        WHERE path = ?1 AND fingerprint IS NOT NULL
+        ORDER BY timestamp DESC
+        LIMIT 1",
+        params![path.to_str().unwrap()],
+        |row| row.get(0),
+    );
+
+    Ok(result.optional()?)
+}
+
+/// Point all entries of a path to a new path.
+pub fn replace_path(path: &Path, new: &Path) -> crate::Result<usize> {
+    let mut conn = database::connect()?;
+    let transaction = conn.transaction()?;
+    let mut count = 0;
+    for table in ["history", "listens"] {
+        count += transaction.execute(
+            &format!("UPDATE {} SET path = ?2 WHERE path = ?1", table),
+            params![path.to_str().unwrap(), new.to_str().unwrap()],
+        )?;
+    }
+
+    transaction.commit()?;
+    Ok(count)
+}
+
+/// Move all entries below a directory to another directory.
+pub fn relocate(root: &Path, new_root: &Path) -> crate::Result<usize> {
+    let root = &normalize(root);
+    let separator = std::path::MAIN_SEPARATOR;
+    let root = format!("{}{}", root.to_str().unwrap().trim_end_matches(separator), separator);
+    let new_root = format!("{}{}", new_root.to_str().unwrap().trim_end_matches(separator), separator);
+
+    let mut conn = database::connect()?;
+    let transaction = conn.transaction()?;
+    let mut count = 0;
+    for table in ["history", "listens"] {
+        count += transaction.execute(
+            &format!("UPDATE {} SET path = ?2 || substr(path, length(?1) + 1)
+                WHERE substr(path, 1, length(?1)) = ?1", table),
+            params![root, new_root],
+        )?;
+    }
+
+    transaction.commit()?;
+    Ok(count)
+}
+
+/// Resolve `.` and `..` in a path without accessing the file system,
+/// as the path may no longer exist.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+
+    normal
+}
+
+pub fn insert_listen(conn: &Connection, listen: &Listen) -> crate::Result<()> {
+    let timestamp = listen.start.duration_since(UNIX_EPOCH)?.as_secs();
+    let duration = listen.duration.map(|duration| duration.as_secs());
+    let metadata = listen.metadata;
+    let origin = metadata.origin.as_ref();
+
     conn.execute(
-        "INSERT INTO history (timestamp, input, path) VALUES (?1, ?2, ?3)",
-        params![timestamp, input.to_str().unwrap(), path.to_str().unwrap()],
+        "INSERT INTO listens (timestamp, listened, duration, completed,
+            path, artist, title, album, origin_name, origin_link)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            timestamp,
+            listen.listened.as_secs(),
+            duration,
+            listen.completed,
+            listen.path.map(|path| path.to_str().unwrap()),
+            metadata.artist,
+            metadata.title,
+            metadata.album,
+            origin.map(|origin| &origin.name),
+            origin.and_then(|origin| origin.link.as_ref()),
+        ],
     )?;
     Ok(())
 }
 
 pub fn find(input: &Path) -> crate::Result<Option<PathBuf>> {
-    let conn = connect()?;
+    let conn = database::connect()?;
     let result = conn.query_row(
         "SELECT path FROM history
         WHERE input = ?1
@@ -58,3 +212,156 @@ pub fn find(input: &Path) -> crate::Result<Option<PathBuf>> {
     let path: Option<String> = result.optional()?;
     Ok(path.map(PathBuf::from))
 }
+
+/// Rank past songs by how closely their inputs,
+/// paths or recorded metadata match a query.
+pub fn search(query: &str) -> crate::Result<Vec<Match>> {
+    let conn = database::connect()?;
+    let mut candidates: HashMap<String, Candidate> = HashMap::new();
+
+    let mut statement = conn.prepare(
+        "SELECT path, input, MAX(timestamp) FROM history
+        GROUP BY path, input")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let candidate = candidates.entry(row.get(0)?).or_default();
+        candidate.fields.push(row.get(1)?);
+        candidate.timestamp = candidate.timestamp.max(row.get(2)?);
+    }
+
+    let mut statement = conn.prepare(
+        "SELECT path, artist, title, MAX(timestamp) FROM listens
+        WHERE path IS NOT NULL
+        GROUP BY path")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let candidate = candidates.entry(row.get(0)?).or_default();
+        let artist: Option<String> = row.get(1)?;
+        let title: Option<String> = row.get(2)?;
+        candidate.name = match (&artist, &title) {
+            (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+            _ => title.clone(),
+        };
+
+        candidate.fields.extend(artist);
+        candidate.fields.extend(title);
+        candidate.timestamp = candidate.timestamp.max(row.get(3)?);
+    }
+
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let mut matches: Vec<_> = candidates.into_iter().filter_map(|(path, candidate)| {
+        let score = candidate.fields.iter().chain([&path])
+            .filter_map(|field| matcher.fuzzy_match(field, query))
+            .max()?;
+
+        let path = PathBuf::from(path);
+        path.is_file().then_some(Match {
+            path,
+            name: candidate.name,
+            score,
+            timestamp: candidate.timestamp,
+        })
+    }).collect();
+
+    matches.sort_by(|a, b| b.score.cmp(&a.score)
+        .then(b.timestamp.cmp(&a.timestamp)));
+    Ok(matches)
+}
+
+pub fn list(filter: &Filter) -> crate::Result<Vec<Entry>> {
+    let since = match filter.since {
+        Some(since) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            now.saturating_sub(since).as_secs()
+        }
+        None => 0,
+    };
+
+    let query = match filter.unique {
+        false => "SELECT timestamp, input, path FROM history
+            WHERE timestamp >= ?1",
+        true => "SELECT MAX(timestamp) AS timestamp, input, path FROM history
+            WHERE timestamp >= ?1
+            GROUP BY path",
+    };
+
+    // Songs in the library index use its metadata instead
+    // of reading the metadata of every song in the history.
+    let conn = database::connect()?;
+    let mut statement = conn.prepare(&format!(
+        "SELECT {}, entry.timestamp, datetime(entry.timestamp, 'unixepoch', 'localtime'),
+            entry.input, entry.path
+        FROM ({}) AS entry
+        LEFT JOIN library ON library.path = entry.path
+        ORDER BY entry.timestamp DESC
+        LIMIT ?2", library::COLUMNS, query))?;
+
+    // Entries can only be limited before filtering.
+    let limit = match (&filter.grep, filter.limit) {
+        (None, Some(limit)) => limit as i64,
+        _ => -1,
+    };
+
+    let mut cache: HashMap<PathBuf, Metadata> = HashMap::new();
+    let mut entries = Vec::new();
+    let mut rows = statement.query(params![since, limit])?;
+    while let Some(row) = rows.next()? {
+        if Some(entries.len()) == filter.limit {
+            break;
+        }
+
+        let input: String = row.get(11)?;
+        let path = PathBuf::from(row.get::<_, String>(12)?);
+        let metadata = match row.get::<_, Option<String>>(0)? {
+            Some(_) => library::song(row)?.metadata,
+            None => cache.entry(path.clone())
+                .or_insert_with_key(|path| find_metadata(path)).clone(),
+        };
+
+        if let Some(grep) = &filter.grep {
+            let fields = [
+                Some(input.as_str()),
+                path.to_str(),
+                metadata.artist.as_deref(),
+                metadata.title.as_deref(),
+                metadata.album.as_deref(),
+            ];
+
+            if !fields.iter().flatten().any(|field| grep.is_match(field)) {
+                continue;
+            }
+        }
+
+        entries.push(Entry {
+            timestamp: row.get(9)?,
+            date: row.get(10)?,
+            input,
+            path,
+            metadata,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Print history entries as tab separated lines.
+pub fn print(filter: &Filter, json: bool, unicode: bool) -> crate::Result<()> {
+    let entries = list(filter)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    for entry in entries {
+        let metadata = &entry.metadata;
+        let title = metadata.title(unicode).unwrap_or("Unknown Title");
+        let name = match metadata.artist(unicode) {
+            Some(artist) => format!("{} - {}", artist, title),
+            None => title.to_string(),
+        };
+
+        println!("{}\t{}\t{}\t{}", entry.date, entry.input, entry.path.display(), name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use rayon::prelude::*;
+use rusqlite::{Connection, params, Row};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::database;
+use crate::metadata::{find_duration, find_metadata, Metadata, Origin, osu_audio};
+
+/// Extensions of audio files that are indexed. Beat Saber
+/// levels use `egg` for Ogg Vorbis files.
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "ogg", "flac", "wav", "m4a", "egg"];
+
+/// Names of the audio files that Clone Hero plays together, in
+/// order of preference for the file that represents the song.
+const STEMS: &[&str] = &[
+    "song", "guitar", "rhythm", "bass", "keys",
+    "drums", "drums_1", "drums_2", "drums_3", "drums_4",
+    "vocals", "vocals_1", "vocals_2",
+];
+
+/// The columns of the library table read by `song`.
+pub const COLUMNS: &str = "library.path, library.mtime, library.size, library.artist,
+    library.title, library.album, library.origin_name, library.origin_link, library.duration";
+
+/// An indexed audio file.
+#[derive(Debug, Serialize)]
+pub struct Song {
+    pub path: PathBuf,
+    // Modification time in seconds since the unix epoch.
+    pub mtime: u64,
+    pub size: u64,
+    pub metadata: Metadata,
+    // The length of the song in seconds if known.
+    pub duration: Option<u64>,
+}
+
+/// Identify file contents by size and a hash of the first 64 KiB.
+/// The fingerprint survives moving or renaming the file.
+pub fn fingerprint(path: &Path) -> io::Result<String> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut head = Vec::new();
+    file.take(64 * 1024).read_to_end(&mut head)?;
+    Ok(format!("{}:{:x}", size, md5::compute(&head)))
+}
+
+/// All files below a directory, following symbolic links.
+/// Paths that are not valid UTF-8 cannot be stored and are skipped.
+pub fn files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    // Links may point to a parent, so each directory is only read once.
+    let mut visited = HashSet::new();
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let canonical = match directory.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => continue,
+        };
+
+        if !visited.insert(canonical) {
+            continue;
+        }
+
+        let entries = match directory.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.to_str().is_none() {
+                eprintln!("Skipping path that is not valid UTF-8: {}", path.display());
+                continue;
+            }
+
+            match path.metadata() {
+                Ok(metadata) if metadata.is_dir() => directories.push(path),
+                Ok(metadata) if metadata.is_file() => files.push(path),
+                Ok(_) => (),
+                Err(_) => eprintln!("Skipping broken link: {}", path.display()),
+            }
+        }
+    }
+
+    files
+}
+
+/// Search the library roots for a file with a fingerprint.
+pub fn find_moved(config: &Config, fingerprint: &str) -> Option<PathBuf> {
+    let size: u64 = fingerprint.split(':').next()?.parse().ok()?;
+    config.library_roots.iter()
+        .flat_map(|root| files(root))
+        .filter(|path| path.metadata().map(|metadata| metadata.len()).ok() == Some(size))
+        .find(|path| self::fingerprint(path).ok().as_deref() == Some(fingerprint))
+}
+
+/// Index the songs below each root. Songs are only read again
+/// if their modification time has changed, unless `full` is set.
+pub fn scan(roots: &[PathBuf], full: bool) -> crate::Result<()> {
+    let mut conn = database::connect()?;
+    for root in roots {
+        let root = canonical_root(root)?;
+        let (indexed, unchanged, removed) = update(&mut conn, &root, full)?;
+        println!("{}: {} indexed, {} unchanged, {} removed.",
+            root.display(), indexed, unchanged, removed);
+    }
+
+    Ok(())
+}
+
+/// Keep the index of each root up to date as files change.
+pub fn watch(roots: &[PathBuf]) -> crate::Result<()> {
+    let roots: Vec<_> = roots.iter().map(|root| canonical_root(root))
+        .collect::<Result<_, _>>()?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, Duration::from_secs(2))?;
+    for root in &roots {
+        watcher.watch(root, RecursiveMode::Recursive)?;
+    }
+
+    let mut conn = database::connect()?;
+    println!("Watching for changes.");
+    loop {
+        // Wait for bursts of changes such as extracting an archive to settle.
+        let mut events = vec![rx.recv()?];
+        while let Ok(event) = rx.recv_timeout(Duration::from_secs(1)) {
+            events.push(event);
+        }
+
+        // Directories to index again and whether to read unchanged songs.
+        let mut changed: BTreeMap<PathBuf, bool> = BTreeMap::new();
+        let mut removed = BTreeSet::new();
+        for event in events {
+            let (from, to) = match event {
+                DebouncedEvent::Create(path) => (None, Some(path)),
+                DebouncedEvent::Write(path) => (None, Some(path)),
+                DebouncedEvent::Remove(path) => (Some(path), None),
+                DebouncedEvent::Rename(from, to) => (Some(from), Some(to)),
+                DebouncedEvent::Rescan => {
+                    changed.extend(roots.iter().map(|root| (root.clone(), false)));
+                    continue;
+                }
+                DebouncedEvent::Error(error, _) => {
+                    eprintln!("Watch error: {}", error);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            // Charts hold the metadata of songs in their directory.
+            for path in from.into_iter().chain(to) {
+                if path.to_str().is_none() {
+                    eprintln!("Skipping path that is not valid UTF-8: {}", path.display());
+                    continue;
+                }
+
+                let directory = path.parent().unwrap().to_path_buf();
+                if path.is_dir() {
+                    changed.entry(path).or_insert(false);
+                } else if is_chart(&path) {
+                    changed.insert(directory, true);
+                } else if !path.exists() {
+                    removed.insert(path);
+                } else if is_audio(&path) {
+                    changed.entry(directory).or_insert(false);
+                }
+            }
+        }
+
+        for path in &removed {
+            let count = remove_below(&conn, path)?;
+            if count > 0 {
+                println!("{}: {} removed.", path.display(), count);
+            }
+        }
+
+        for (directory, full) in changed {
+            if !directory.is_dir() {
+                continue;
+            }
+
+            let (indexed, _, removed) = update(&mut conn, &directory, full)?;
+            if indexed + removed > 0 {
+                println!("{}: {} indexed, {} removed.", directory.display(), indexed, removed);
+            }
+        }
+    }
+}
+
+/// The canonical path of a library root, which must be valid UTF-8 to be stored.
+fn canonical_root(root: &Path) -> crate::Result<PathBuf> {
+    let root = root.canonicalize()?;
+    match root.to_str() {
+        Some(_) => Ok(root),
+        None => Err(format!("Library root is not valid UTF-8: {}", root.display()).into()),
+    }
+}
+
+/// Index the songs below a directory and remove songs that no longer exist.
+/// Returns the number of songs indexed, unchanged and removed.
+fn update(conn: &mut Connection, root: &Path, full: bool) -> crate::Result<(usize, usize, usize)> {
+    let known = indexed(conn, root)?;
+    let paths = songs(root);
+
+    let changed: Vec<_> = paths.iter().filter(|path| {
+        let mtime = stat(path).map(|(mtime, _)| mtime);
+        full || known.get(path.to_str().unwrap()) != mtime.as_ref()
+    }).collect();
+
+    let songs: Vec<_> = changed.par_iter()
+        .filter_map(|path| index(path)).collect();
+
+    let current: HashSet<_> = paths.iter().map(|path| path.to_str().unwrap()).collect();
+    let removed: Vec<_> = known.keys()
+        .filter(|path| !current.contains(path.as_str())).collect();
+
+    let transaction = conn.transaction()?;
+    for song in &songs {
+        store(&transaction, song)?;
+    }
+
+    for path in &removed {
+        remove(&transaction, Path::new(path))?;
+    }
+
+    transaction.commit()?;
+    Ok((songs.len(), paths.len() - changed.len(), removed.len()))
+}
+
+/// Read the metadata of an audio file.
+pub fn index(path: &Path) -> Option<Song> {
+    let (mtime, size) = stat(path)?;
+    Some(Song {
+        path: path.to_path_buf(),
+        mtime,
+        size,
+        metadata: find_metadata(path),
+        duration: find_duration(path).map(|duration| duration.as_secs()),
+    })
+}
+
+/// Read a song from a row selecting `COLUMNS`.
+pub fn song(row: &Row) -> rusqlite::Result<Song> {
+    let link = row.get(7)?;
+    let origin = row.get::<_, Option<String>>(6)?.map(|name| Origin { name, link });
+
+    Ok(Song {
+        path: PathBuf::from(row.get::<_, String>(0)?),
+        mtime: row.get(1)?,
+        size: row.get(2)?,
+        metadata: Metadata {
+            artist: row.get(3)?,
+            title: row.get(4)?,
+            album: row.get(5)?,
+            origin,
+            ..Metadata::default()
+        },
+        duration: row.get(8)?,
+    })
+}
+
+pub fn store(conn: &Connection, song: &Song) -> crate::Result<()> {
+    let metadata = &song.metadata;
+    let origin = metadata.origin.as_ref();
+    conn.execute(
+        "INSERT INTO library (path, mtime, size,
+            artist, title, album, origin_name, origin_link, duration)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ON CONFLICT (path) DO UPDATE SET mtime = ?2, size = ?3, artist = ?4,
+            title = ?5, album = ?6, origin_name = ?7, origin_link = ?8, duration = ?9",
+        params![
+            song.path.to_str().unwrap(),
+            song.mtime,
+            song.size,
+            metadata.artist,
+            metadata.title,
+            metadata.album,
+            origin.map(|origin| &origin.name),
+            origin.and_then(|origin| origin.link.as_ref()),
+            song.duration,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn remove(conn: &Connection, path: &Path) -> crate::Result<()> {
+    conn.execute("DELETE FROM library WHERE path = ?1", params![path.to_str().unwrap()])?;
+    Ok(())
+}
+
+/// The indexed songs with an origin link.
+pub fn find_origin(link: &str) -> crate::Result<Vec<PathBuf>> {
+    let conn = database::connect()?;
+    let mut statement = conn.prepare("SELECT path FROM library WHERE origin_link = ?1")?;
+    let rows = statement.query_map(params![link], |row| row.get::<_, String>(0))?;
+    let paths = rows.collect::<Result<Vec<_>, _>>()?;
+    Ok(paths.into_iter().map(PathBuf::from).collect())
+}
+
+/// Remove a song or all songs below a directory.
+fn remove_below(conn: &Connection, path: &Path) -> crate::Result<usize> {
+    Ok(conn.execute(
+        "DELETE FROM library WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        params![path.to_str().unwrap(), directory_prefix(path)],
+    )?)
+}
+
+/// The modification times of indexed songs below a directory.
+fn indexed(conn: &Connection, root: &Path) -> crate::Result<HashMap<String, u64>> {
+    let mut statement = conn.prepare(
+        "SELECT path, mtime FROM library
+        WHERE substr(path, 1, length(?1)) = ?1")?;
+    let rows = statement.query_map(params![directory_prefix(root)],
+        |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// A directory path ending with a separator.
+fn directory_prefix(directory: &Path) -> String {
+    let separator = std::path::MAIN_SEPARATOR;
+    format!("{}{}", directory.to_str().unwrap().trim_end_matches(separator), separator)
+}
+
+/// The modification time and size of a file.
+fn stat(path: &Path) -> Option<(u64, u64)> {
+    let metadata = path.metadata().ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((mtime.as_secs(), metadata.len()))
+}
+
+pub fn is_audio(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    match extension {
+        Some(extension) => AUDIO_EXTENSIONS.iter()
+            .any(|audio| audio.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+/// Whether a file describes the songs in its directory.
+fn is_chart(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    matches!(extension, Some("osu" | "sm" | "ssc" | "qua")) || is_named(path, &["song.ini", "info.dat"])
+}
+
+fn is_named(path: &Path, names: &[&str]) -> bool {
+    match path.file_name() {
+        Some(name) => names.iter().any(|other| name.eq_ignore_ascii_case(other)),
+        None => false,
+    }
+}
+
+/// Audio files below a directory. Beatmap folders also contain
+/// hitsounds, so only audio referenced by a beatmap is included there.
+/// Clone Hero songs split into stems are included once.
+pub fn songs(root: &Path) -> Vec<PathBuf> {
+    let mut beatmaps = HashMap::new();
+    files(root).into_iter().filter(|path| is_audio(path)).filter(|path| {
+        let directory = path.parent().unwrap().to_path_buf();
+        if directory.join("song.ini").is_file() {
+            let stems = stems(path);
+            return stems.first().and_then(|stem| stem.file_name()) == path.file_name();
+        }
+
+        let audio = beatmaps.entry(directory)
+            .or_insert_with_key(|directory| beatmap_audio(directory));
+        match audio {
+            None => true,
+            Some(audio) => {
+                let name = path.file_name().unwrap().to_string_lossy();
+                audio.contains(&name.to_lowercase())
+            }
+        }
+    }).collect()
+}
+
+/// The lowercase names of audio files used by the beatmaps in a directory.
+fn beatmap_audio(directory: &Path) -> Option<HashSet<String>> {
+    let mut audio = HashSet::new();
+    let mut beatmap = false;
+    for entry in directory.read_dir().ok()?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("osu") {
+            continue;
+        }
+
+        beatmap = true;
+        let string = std::fs::read_to_string(&path).unwrap_or_default();
+        if let Some(file) = osu_audio(&string) {
+            audio.insert(file.to_lowercase());
+        }
+    }
+
+    beatmap.then_some(audio)
+}
+
+/// The stems of a Clone Hero song that are played together with a stem,
+/// with the file representing the song first. Other songs have no stems.
+pub fn stems(path: &Path) -> Vec<PathBuf> {
+    let rank = |path: &Path| {
+        let name = path.file_stem()?.to_str()?.to_lowercase();
+        STEMS.iter().position(|stem| *stem == name)
+    };
+
+    let directory = match path.parent() {
+        Some(directory) if directory.join("song.ini").is_file() => directory,
+        _ => return Vec::new(),
+    };
+
+    let entries = match directory.read_dir() {
+        Ok(entries) if rank(path).is_some() => entries,
+        _ => return Vec::new(),
+    };
+
+    let mut stems: Vec<_> = entries.flatten().map(|entry| entry.path())
+        .filter(|stem| is_audio(stem) && rank(stem).is_some())
+        .collect();
+    stems.sort_by_key(|stem| rank(stem));
+    stems
+}
@@ -1,33 +1,72 @@
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
+use regex::Regex;
 
 use crate::config::Config;
 use crate::daemon::Message;
 
+mod alias;
 mod daemon;
 mod config;
+mod database;
+mod export;
 mod history;
+mod library;
 mod metadata;
+mod osu;
+mod playlist;
+mod prompt;
+mod search;
+mod shuffle;
+mod stats;
+#[cfg(test)]
+mod testing;
 
 pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
 
 #[derive(Parser)]
 #[clap(author, version, about)]
 struct Cli {
+    /// The name of the player to control.
+    #[clap(long, global = true)]
+    player: Option<String>,
     #[clap(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Queue a song to play.
+    /// Queue a song, M3U playlist, alias, smart playlist (`@name`), osu! collection
+    /// (`osu-collection:name`) or osu! beatmap set (`osu:id` or a beatmap URL) to play.
     Play {
         path: PathBuf,
         /// Clear the queue and play immediately.
         #[clap(long)]
         now: bool,
+        /// Pick the best match instead of asking when several songs match.
+        #[clap(long)]
+        first: bool,
+        /// Only play the preview of each song, without scrobbling or recording it.
+        #[clap(long)]
+        preview: bool,
+    },
+    /// Queue the previews of the songs below a directory, such as a folder of beatmaps or simfiles.
+    Sample {
+        directory: PathBuf,
+        /// Clear the queue and play immediately.
+        #[clap(long)]
+        now: bool,
+    },
+    /// Keep the queue filled with random songs from the library until stopped.
+    Shuffle {
+        /// A smart playlist query or `@name` of a smart playlist to choose songs from.
+        query: Vec<String>,
+        /// Stop adding random songs to the queue.
+        #[clap(long, conflicts_with = "query")]
+        off: bool,
     },
     /// Pause or unpause the current song.
     Pause,
@@ -35,14 +74,198 @@ enum Commands {
     Stop,
     /// Skip to the next song.
     Skip,
+    /// Print the current song and queue.
+    Status {
+        /// Print the status as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// List previously played songs.
+    #[clap(args_conflicts_with_subcommands = true)]
+    History {
+        #[clap(subcommand)]
+        command: Option<HistoryCommands>,
+        /// Maximum number of entries to print.
+        #[clap(long)]
+        limit: Option<usize>,
+        /// Only print entries within a duration (e.g. `30m`, `12h`, `7d`).
+        #[clap(long, parse(try_from_str = parse_age))]
+        since: Option<Duration>,
+        /// Only print entries matching a regular expression.
+        #[clap(long)]
+        grep: Option<Regex>,
+        /// Only print the latest entry for each song.
+        #[clap(long)]
+        unique: bool,
+        /// Print entries as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print listening statistics.
+    Stats {
+        /// Only count listens within a duration (e.g. `30m`, `12h`, `7d`).
+        #[clap(long, parse(try_from_str = parse_age))]
+        since: Option<Duration>,
+        /// Number of entries in each top list.
+        #[clap(long, default_value = "10")]
+        limit: usize,
+        /// Print statistics as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Manage named shortcuts to songs and playlists.
+    Alias {
+        #[clap(subcommand)]
+        command: AliasCommands,
+    },
+    /// Manage smart playlists defined by queries over the library.
+    Playlist {
+        #[clap(subcommand)]
+        command: PlaylistCommands,
+    },
+    /// Use data from the osu! installation.
+    Osu {
+        #[clap(subcommand)]
+        command: OsuCommands,
+    },
+    /// Index the songs below directories for searching.
+    Scan {
+        /// Directories to index. Defaults to the configured library roots.
+        roots: Vec<PathBuf>,
+        /// Read every song again, even if unchanged.
+        #[clap(long)]
+        full: bool,
+        /// Keep the index up to date as files change after scanning.
+        #[clap(long)]
+        watch: bool,
+    },
+    /// Search the library index (e.g. `artist:camellia origin:osu`).
+    Search {
+        #[clap(required = true)]
+        query: Vec<String>,
+        /// Maximum number of songs to print.
+        #[clap(long)]
+        limit: Option<usize>,
+        /// Print songs as JSON.
+        #[clap(long)]
+        json: bool,
+        /// Clear the queue and play the matching songs.
+        #[clap(long, conflicts_with = "queue")]
+        play: bool,
+        /// Add the matching songs to the queue.
+        #[clap(long)]
+        queue: bool,
+    },
+    /// Manage the history database.
+    Db {
+        #[clap(subcommand)]
+        command: DbCommands,
+    },
     /// Start the player daemon. This should not be used directly.
     Daemon,
     /// Print the location of the configuration directory.
     Config,
 }
 
+#[derive(Subcommand)]
+enum HistoryCommands {
+    /// Point history entries below a moved directory to its new location.
+    Relocate {
+        old_root: PathBuf,
+        new_root: PathBuf,
+    },
+    /// Print all listens in a format.
+    Export {
+        #[clap(long, arg_enum, default_value = "json")]
+        format: export::Format,
+    },
+    /// Add listens from a file, skipping listens already present.
+    Import {
+        path: PathBuf,
+        #[clap(long, arg_enum, default_value = "json")]
+        format: export::Format,
+    },
+}
+
+#[derive(Subcommand)]
+enum AliasCommands {
+    /// Create or replace an alias for a song or M3U playlist.
+    Add {
+        name: String,
+        path: PathBuf,
+    },
+    /// Print all aliases.
+    List,
+    /// Remove an alias.
+    Rm {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PlaylistCommands {
+    /// Create or replace a smart playlist. Queries are made of terms such as
+    /// `artist:camellia`, `origin:osu`, `plays>=5`, `played>7d`, `sort:-plays` and `limit:20`.
+    Create {
+        name: String,
+        query: String,
+    },
+    /// Print all smart playlists.
+    List,
+    /// Print the songs currently in a smart playlist.
+    Show {
+        name: String,
+        /// Print songs as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Remove a smart playlist.
+    Rm {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum OsuCommands {
+    /// Print the in-game collections and their number of beatmaps.
+    Collections {
+        /// Print collections and their installed beatmaps as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Save songs as a collection, merging into any collection with the same name unless `--replace` is given.
+    /// osu! must be closed, as it overwrites collections when it exits.
+    #[clap(group(ArgGroup::new("songs").required(true)))]
+    Export {
+        name: String,
+        /// Save the current song and queue.
+        #[clap(long, group = "songs")]
+        queue: bool,
+        /// Save the songs of a smart playlist.
+        #[clap(long, group = "songs")]
+        playlist: Option<String>,
+        /// Save a number of the most played songs from beatmaps.
+        #[clap(long, group = "songs")]
+        top: Option<usize>,
+        /// Replace the beatmaps of an existing collection instead of adding to them.
+        #[clap(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Upgrade the database schema to the latest version.
+    Migrate {
+        /// Check pending migrations without changing the database.
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
 fn main() -> crate::Result<()> {
-    let config = &match config::load() {
+    let cli = Cli::parse();
+    let config = match config::load() {
         Ok(config) => config,
         Err(error) => {
             eprintln!("Config error (using defaults): {}", error);
@@ -50,12 +273,119 @@ fn main() -> crate::Result<()> {
         }
     };
 
-    let cli = Cli::parse();
+    let config = &match &cli.player {
+        Some(player) => config.select_player(player)?,
+        None => config,
+    };
+
     match cli.command {
-        Commands::Play { path, now } => play(config, path, now),
+        Commands::Play { path, now, first, preview } => play(config, path, now, first, preview),
+        Commands::Sample { directory, now } => {
+            let mut songs = library::songs(&directory.canonicalize()?);
+            songs.sort();
+            match songs.is_empty() {
+                false => queue(config, songs, now, true),
+                true => Err("Directory has no songs.".into()),
+            }
+        }
+        Commands::Shuffle { off: true, .. } => match daemon::query_status(config)? {
+            Some(_) => daemon::send(config, &Message::Shuffle { query: None }),
+            None => Ok(()),
+        },
+        Commands::Shuffle { query, off: false } => {
+            let query = query.join(" ");
+            match shuffle::candidates(&query)?.is_empty() {
+                false => daemon::send(config, &Message::Shuffle { query: Some(query) }),
+                true => Err("No songs match the query.".into()),
+            }
+        }
         Commands::Pause => daemon::send(config, &Message::Pause),
         Commands::Stop => daemon::send(config, &Message::Stop),
         Commands::Skip => daemon::send(config, &Message::Skip),
+        Commands::Status { json } => status(config, json),
+        Commands::History { command: Some(command), .. } => match command {
+            HistoryCommands::Relocate { old_root, new_root } => {
+                let old_root = std::env::current_dir()?.join(old_root);
+                let count = history::relocate(&old_root, &new_root.canonicalize()?)?;
+                println!("Relocated {} entries.", count);
+                Ok(())
+            }
+            HistoryCommands::Export { format } => export::export(&format),
+            HistoryCommands::Import { path, format } => export::import(&path, &format),
+        },
+        Commands::History { command: None, limit, since, grep, unique, json } => {
+            let filter = history::Filter { limit, since, grep, unique };
+            history::print(&filter, json, config.display_unicode)
+        }
+        Commands::Stats { since, limit, json } => stats::print(since, limit, json),
+        Commands::Alias { command } => match command {
+            AliasCommands::Add { name, path } => alias::add(&name, &path.canonicalize()?),
+            AliasCommands::List => alias::print(),
+            AliasCommands::Rm { name } => match alias::remove(&name)? {
+                true => Ok(()),
+                false => Err(format!("Alias does not exist: {}", name).into()),
+            },
+        },
+        Commands::Playlist { command } => match command {
+            PlaylistCommands::Create { name, query } => playlist::create(&name, &query),
+            PlaylistCommands::List => playlist::print(),
+            PlaylistCommands::Show { name, json } => match playlist::smart_songs(&name)? {
+                Some(songs) => search::print(&songs, json),
+                None => Err(format!("Smart playlist does not exist: {}", name).into()),
+            },
+            PlaylistCommands::Rm { name } => match playlist::remove(&name)? {
+                true => Ok(()),
+                false => Err(format!("Smart playlist does not exist: {}", name).into()),
+            },
+        },
+        Commands::Osu { command } => match command {
+            OsuCommands::Collections { json } => osu::print_collections(config, json),
+            OsuCommands::Export { name, queue, playlist, top, replace } => {
+                let paths = match (queue, playlist, top) {
+                    (true, _, _) => {
+                        let status = daemon::query_status(config)?
+                            .ok_or("Player is not running.")?;
+                        status.song.map(|song| song.path).into_iter()
+                            .chain(status.queue).collect()
+                    }
+                    (_, Some(playlist), _) => playlist::smart_songs(&playlist)?
+                        .ok_or_else(|| format!("Smart playlist does not exist: {}", playlist))?
+                        .into_iter().map(|song| song.path).collect(),
+                    // Only songs from beatmaps can be added to collections.
+                    (_, _, Some(top)) => stats::top_songs(metadata::OSU_ORIGIN, top)?,
+                    _ => unreachable!(),
+                };
+
+                osu::export_collection(config, &name, &paths, replace)
+            }
+        },
+        Commands::Scan { roots, full, watch } => {
+            let roots = match roots.is_empty() {
+                false => &roots,
+                true => &config.library_roots,
+            };
+
+            if roots.is_empty() {
+                return Err("No directories given and no library roots configured.".into());
+            }
+
+            library::scan(roots, full)?;
+            match watch {
+                false => Ok(()),
+                true => library::watch(roots),
+            }
+        }
+        Commands::Search { query, limit, json, play, queue: add } => {
+            let songs = search::search(&query.join(" "), limit)?;
+            match play || add {
+                false => search::print(&songs, json),
+                true if songs.is_empty() => Err("No songs match the query.".into()),
+                true => queue(config, songs.into_iter().map(|song| song.path).collect(), play, false),
+            }
+        }
+        Commands::Db { command } => match command {
+            DbCommands::Migrate { dry_run } => database::print_migrate(dry_run),
+        },
         Commands::Daemon => daemon::bootstrap(config),
         Commands::Config => {
             println!("{}", config::directory().display());
@@ -64,13 +394,19 @@ fn main() -> crate::Result<()> {
     }
 }
 
-fn play(config: &Config, input: PathBuf, now: bool) -> crate::Result<()> {
-    let path = match input.exists() {
-        true => Some(input.clone()),
-        false => history::find(&input)?,
-    };
+fn play(config: &Config, input: PathBuf, now: bool, first: bool, preview: bool) -> crate::Result<()> {
+    if let Some(name) = input.to_str().and_then(|input| input.strip_prefix('@')) {
+        let songs = playlist::smart_songs(name)?
+            .ok_or_else(|| format!("Smart playlist does not exist: {}", name))?;
+        let paths = songs.into_iter().map(|song| song.path).collect();
+        return queue_all(config, paths, now, preview);
+    }
+
+    if let Some(name) = input.to_str().and_then(|input| input.strip_prefix("osu-collection:")) {
+        return queue_all(config, osu::collection_songs(config, name)?, now, preview);
+    }
 
-    let path = path
+    let path = resolve(config, &input, first)?
         .map(|path| path.canonicalize())
         .transpose()?;
 
@@ -78,8 +414,180 @@ fn play(config: &Config, input: PathBuf, now: bool) -> crate::Result<()> {
         None => Err("Audio file does not exist.".into()),
         Some(path) if !path.is_file() => Err("Path provided is not a file.".into()),
         Some(path) => {
-            history::insert(&input, &path)?;
-            daemon::send(config, &Message::Play { path, now })
+            // Previews are not recorded in the history.
+            if !preview {
+                history::insert(&input, &path)?;
+            }
+
+            let paths = match playlist::is_m3u(&path) {
+                false => vec![path],
+                true => existing(playlist::read_m3u(&path)?),
+            };
+
+            queue(config, paths, now, preview)
+        }
+    }
+}
+
+/// Find the song or playlist referred to by an input.
+fn resolve(config: &Config, input: &Path, first: bool) -> crate::Result<Option<PathBuf>> {
+    if input.exists() {
+        return Ok(Some(input.to_path_buf()));
+    }
+
+    if let Some(path) = alias::find(&input.to_string_lossy())? {
+        return Ok(Some(path));
+    }
+
+    if let Some(link) = osu::parse_link(&input.to_string_lossy()) {
+        return choose_song(osu::link_songs(config, &link)?, first);
+    }
+
+    match history::find(input)? {
+        Some(path) if !path.exists() => find_moved(config, &path),
+        Some(path) => Ok(Some(path)),
+        None => find_fuzzy(input, first),
+    }
+}
+
+/// Ask which of several songs to play unless picking the first.
+fn choose_song(mut paths: Vec<PathBuf>, first: bool) -> crate::Result<Option<PathBuf>> {
+    if first || paths.len() <= 1 {
+        return Ok(paths.into_iter().next());
+    }
+
+    let options: Vec<_> = paths.iter().map(|path| {
+        let name = path.file_name().unwrap().to_string_lossy();
+        match metadata::find_metadata(path).title {
+            Some(title) => format!("{} ({})", title, name),
+            None => name.to_string(),
+        }
+    }).collect();
+
+    let choice = prompt::choose("Several songs match:", &options)?;
+    Ok(choice.map(|index| paths.swap_remove(index)))
+}
+
+/// Remove songs that do not exist from a playlist.
+fn existing(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths.into_iter().filter(|path| {
+        let exists = path.is_file();
+        if !exists {
+            eprintln!("Skipping missing song: {}", path.display());
+        }
+
+        exists
+    }).collect()
+}
+
+/// Send the songs of a playlist that exist to the daemon.
+fn queue_all(config: &Config, paths: Vec<PathBuf>, now: bool, preview: bool) -> crate::Result<()> {
+    let paths = existing(paths);
+    match paths.is_empty() {
+        false => queue(config, paths, now, preview),
+        true => Err("Playlist has no songs.".into()),
+    }
+}
+
+/// Send songs to the daemon in order.
+fn queue(config: &Config, paths: Vec<PathBuf>, now: bool, preview: bool) -> crate::Result<()> {
+    for (index, path) in paths.into_iter().enumerate() {
+        let now = now && index == 0;
+        daemon::send(config, &Message::Play { path, now, preview })?;
+    }
+
+    Ok(())
+}
+
+/// Find a song from the history that has been moved.
+fn find_moved(config: &Config, path: &Path) -> crate::Result<Option<PathBuf>> {
+    let moved = history::find_fingerprint(path)?
+        .and_then(|fingerprint| library::find_moved(config, &fingerprint));
+
+    if let Some(moved) = &moved {
+        eprintln!("Found moved song: {}", moved.display());
+        history::replace_path(path, moved)?;
+    }
+
+    Ok(moved)
+}
+
+/// Find a past song that closely matches an input.
+fn find_fuzzy(input: &Path, first: bool) -> crate::Result<Option<PathBuf>> {
+    let mut matches = history::search(&input.to_string_lossy())?;
+    let best = match matches.first() {
+        Some(best) => best.score,
+        None => return Ok(None),
+    };
+
+    // Only offer matches scoring close to the best.
+    matches.retain(|candidate| candidate.score * 5 >= best * 4);
+    matches.truncate(10);
+    if first || matches.len() == 1 {
+        return Ok(Some(matches.swap_remove(0).path));
+    }
+
+    let options: Vec<_> = matches.iter().map(|candidate| match &candidate.name {
+        Some(name) => format!("{} ({})", name, candidate.path.display()),
+        None => candidate.path.display().to_string(),
+    }).collect();
+
+    let choice = prompt::choose("Several songs match:", &options)?;
+    Ok(choice.map(|index| matches.swap_remove(index).path))
+}
+
+fn status(config: &Config, json: bool) -> crate::Result<()> {
+    let status = match daemon::query_status(config)? {
+        Some(status) => status,
+        None => return Err("Player is not running.".into()),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    match &status.song {
+        None => println!("Idle"),
+        Some(song) => {
+            let state = match song.paused {
+                false => "Playing",
+                true => "Paused",
+            };
+
+            let metadata = &song.metadata;
+            let artist = metadata.artist(config.display_unicode).unwrap_or("Unknown Artist");
+            let title = metadata.title(config.display_unicode).unwrap_or("Unknown Title");
+            let elapsed = song.elapsed as u64;
+            println!("{}: {} - {} [{}:{:02}]", state, artist, title, elapsed / 60, elapsed % 60);
+            println!("{}", song.path.display());
         }
     }
+
+    println!("Volume: {}", status.volume);
+    for (index, path) in status.queue.iter().enumerate() {
+        println!("{:>3}. {}", index + 1, path.display());
+    }
+
+    Ok(())
+}
+
+/// Parse a duration such as `90s`, `30m`, `12h`, `7d` or `2w`.
+fn parse_age(string: &str) -> crate::Result<Duration> {
+    let split = string.len() - string.chars().last().map_or(0, char::len_utf8);
+    let (count, unit) = string.split_at(split);
+    let count: u64 = count.parse()?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => return Err("Duration must end with one of: s, m, h, d, w".into()),
+    };
+
+    match count.checked_mul(seconds) {
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Err("Duration is too long.".into()),
+    }
 }
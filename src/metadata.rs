@@ -1,56 +1,198 @@
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::{StandardTagKey, Value};
-use symphonia::core::probe::Hint;
+use symphonia::core::probe::{Hint, ProbeResult};
 
-#[derive(Debug, Default)]
+/// The origin name of songs from osu! beatmaps.
+pub const OSU_ORIGIN: &str = "osu! Beatmap";
+
+/// The origin name of songs from StepMania simfiles.
+pub const STEPMANIA_ORIGIN: &str = "StepMania";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Metadata {
     pub artist: Option<String>,
     pub title: Option<String>,
     pub album: Option<String>,
     pub origin: Option<Origin>,
+    // The artist and title in their original script
+    // when the fields above are romanised.
+    pub artist_unicode: Option<String>,
+    pub title_unicode: Option<String>,
+    // The author of the chart.
+    pub creator: Option<String>,
+    // The difficulty name of the chart.
+    pub version: Option<String>,
+    pub tags: Vec<String>,
+    // The identifier of the chart on its origin.
+    pub chart_id: Option<String>,
+    // The part of the song played when browsing charts.
+    pub preview: Option<Preview>,
+    pub subtitle: Option<String>,
+    pub genre: Option<String>,
+    // Images shown with the chart.
+    pub banner: Option<PathBuf>,
+    pub background: Option<PathBuf>,
+    pub bpm: Option<Bpm>,
+    pub year: Option<u32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Origin {
     pub name: String,
-    pub link: String,
+    // The web page of the origin, if it has one.
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preview {
+    pub start_seconds: f64,
+    pub length_seconds: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bpm {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Metadata {
+    /// The artist, in its original script if `unicode` is set and it is known.
+    pub fn artist(&self, unicode: bool) -> Option<&str> {
+        match (unicode, &self.artist_unicode) {
+            (true, Some(artist)) => Some(artist),
+            _ => self.artist.as_deref(),
+        }
+    }
+
+    /// The title, in its original script if `unicode` is set and it is known.
+    pub fn title(&self, unicode: bool) -> Option<&str> {
+        match (unicode, &self.title_unicode) {
+            (true, Some(title)) => Some(title),
+            _ => self.title.as_deref(),
+        }
+    }
 }
 
 pub fn find_metadata(path: &Path) -> Metadata {
-    let candidates = [
-        osu(path),
-        stepmania(path),
-        file_tags(path),
-    ];
-
-    for candidate in candidates {
-        if let Some(metadata) = candidate {
-            return metadata;
+    // Later providers are only tried when earlier ones find nothing.
+    let providers: [fn(&Path) -> Option<Metadata>; 6] =
+        [osu, stepmania, clone_hero, beat_saber, quaver, file_tags];
+
+    providers.iter().find_map(|provider| provider(path)).unwrap_or_else(|| {
+        // Default metadata.
+        let title = path.file_stem().unwrap().to_string_lossy();
+        let title = Some(title.to_string());
+        Metadata { title, ..Metadata::default() }
+    })
+}
+
+/// Find the chart beside a song that plays it. Songs that no chart
+/// references use the first chart so that metadata is still found.
+/// Charts with extensions earlier in the list are preferred.
+fn find_chart(
+    path: &Path,
+    extensions: &[&str],
+    audio: fn(&str) -> Option<String>,
+) -> Option<(PathBuf, String)> {
+    let name = path.file_name()?.to_string_lossy();
+    let mut charts: Vec<_> = path.parent()?.read_dir().ok()?
+        .flatten().map(|entry| entry.path())
+        .filter_map(|chart| {
+            let extension = chart.extension().and_then(OsStr::to_str)?;
+            let rank = extensions.iter().position(|other| *other == extension)?;
+            Some((rank, chart))
+        })
+        .collect();
+
+    charts.sort();
+    let charts = charts.into_iter().map(|(_, chart)| chart);
+    let mut fallback = None;
+    for chart in charts {
+        let string = match read_file_string(&chart) {
+            Some(string) => string,
+            None => continue,
+        };
+
+        let file = audio(&string);
+        if let Some(file) = file.as_deref().map(Path::new).and_then(Path::file_name) {
+            if file.to_string_lossy().eq_ignore_ascii_case(&name) {
+                return Some((chart, string));
+            }
+        }
+
+        fallback.get_or_insert((chart, string));
+    }
+
+    fallback
+}
+
+/// The audio file name of an osu! beatmap.
+pub fn osu_audio(string: &str) -> Option<String> {
+    let sections = osu_sections(string);
+    let audio = sections.get("General")?.get("AudioFilename")?;
+    Some(audio.to_string())
+}
+
+/// The non-empty `key:value` fields of the sections of an osu! beatmap
+/// that describe the beatmap, before the timing points and objects.
+fn osu_sections(string: &str) -> HashMap<&str, HashMap<&str, &str>> {
+    let mut sections: HashMap<_, HashMap<_, _>> = HashMap::new();
+    let mut section = "";
+    for line in string.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+
+        if !matches!(section, "General" | "Metadata") {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            if !value.is_empty() {
+                sections.entry(section).or_default().insert(key.trim(), value);
+            }
         }
     }
 
-    // Default metadata.
-    let title = path.file_stem().unwrap().to_string_lossy();
-    let title = Some(title.to_string());
-    Metadata { title, ..Metadata::default() }
+    sections
 }
 
-fn find_extension(extension: &str, directory: &Path) -> Option<PathBuf> {
-    let extension = OsStr::new(extension);
-    for element in directory.read_dir().ok()? {
-        let path = element.ok()?.path();
-        if path.extension() == Some(extension) {
-            return Some(path);
+/// The audio file name of a StepMania simfile.
+fn stepmania_audio(string: &str) -> Option<String> {
+    let fields = stepmania_fields(string);
+    Some(fields.get("MUSIC")?.to_string())
+}
+
+/// The non-empty `#KEY:value;` fields of the song header of a StepMania
+/// simfile, before the fields of the first chart.
+fn stepmania_fields(string: &str) -> HashMap<&str, &str> {
+    let re = Regex::new(r"#([A-Za-z0-9]+):([^;]*);").unwrap();
+    let mut fields = HashMap::new();
+    for captures in re.captures_iter(string) {
+        let key = captures.get(1).unwrap().as_str();
+        if matches!(key, "NOTES" | "NOTEDATA") {
+            break;
+        }
+
+        let value = captures.get(2).unwrap().as_str().trim();
+        if !value.is_empty() {
+            fields.entry(key).or_insert(value);
         }
     }
 
-    None
+    fields
 }
 
 fn read_file_string(path: &Path) -> Option<String> {
@@ -72,45 +214,295 @@ fn find_regex_match(pattern: &str, text: &str) -> Option<String> {
 
 /// https://osu.ppy.sh/home
 fn osu(path: &Path) -> Option<Metadata> {
+    // Installed beatmaps are read from `osu!.db` instead of every beatmap file in their folder.
+    crate::osu::beatmap_metadata(path).or_else(|| osu_file(path))
+}
+
+fn osu_file(path: &Path) -> Option<Metadata> {
     let directory = path.parent()?;
-    let path = find_extension("osu", directory)?;
-    let string = &read_file_string(&path)?;
+    let (_, string) = &find_chart(path, &["osu"], osu_audio)?;
+    let sections = osu_sections(string);
+    let empty = HashMap::new();
+    let general = sections.get("General").unwrap_or(&empty);
+    let metadata = sections.get("Metadata").unwrap_or(&empty);
+    let field = |key: &str| metadata.get(key).map(|value| value.to_string());
+
+    // Unsubmitted beatmaps have negative identifiers.
+    let id = |key: &str| metadata.get(key)
+        .filter(|value| value.parse::<u32>().is_ok())
+        .map(|value| value.to_string());
 
     let parent = directory.file_stem().unwrap();
     let origin = Option::or(
-        find_regex_match(r"BeatmapSetID:([^\n]+)", string),
+        id("BeatmapSetID"),
         find_regex_match(r"(\d+)", &parent.to_string_lossy()),
     );
 
     let origin = origin.map(|origin| Origin {
-        name: "osu! Beatmap".to_string(),
-        link: format!("https://osu.ppy.sh/beatmapsets/{}", origin),
+        name: OSU_ORIGIN.to_string(),
+        link: Some(format!("https://osu.ppy.sh/beatmapsets/{}", origin)),
     });
 
+    // Beatmaps without a preview have a negative preview time.
+    let preview = general.get("PreviewTime")
+        .and_then(|time| time.parse::<u64>().ok())
+        .map(|time| Preview { start_seconds: time as f64 / 1000.0, length_seconds: None });
+
+    let tags = metadata.get("Tags").map(|tags| tags.split_whitespace().map(str::to_string).collect());
     Some(Metadata {
-        artist: find_regex_match(r"Artist:([^\n]+)", string),
-        title: find_regex_match(r"Title:([^\n]+)", string),
-        album: None,
+        artist: field("Artist"),
+        title: field("Title"),
+        // The media the song is from is the closest to an album.
+        album: field("Source"),
         origin,
+        artist_unicode: field("ArtistUnicode"),
+        title_unicode: field("TitleUnicode"),
+        creator: field("Creator"),
+        version: field("Version"),
+        tags: tags.unwrap_or_default(),
+        chart_id: id("BeatmapID"),
+        preview,
+        ..Metadata::default()
     })
 }
 
 /// https://www.stepmania.com
 fn stepmania(path: &Path) -> Option<Metadata> {
     let directory = path.parent()?;
-    let path = find_extension("sm", directory)?;
-    let string = &read_file_string(&path)?;
+    // StepMania 5 prefers the newer format when a song has both.
+    let (_, string) = &find_chart(path, &["ssc", "sm"], stepmania_audio)?;
+    let fields = stepmania_fields(string);
+    let field = |key: &str| fields.get(key).map(|value| value.to_string());
+    let seconds = |key: &str| fields.get(key).and_then(|value| value.parse::<f64>().ok());
+
+    // The transliterated fields are romanised when the others are not.
+    let (artist, artist_unicode) = match field("ARTISTTRANSLIT") {
+        Some(artist) => (Some(artist), field("ARTIST")),
+        None => (field("ARTIST"), None),
+    };
+
+    let (title, title_unicode) = match field("TITLETRANSLIT") {
+        Some(title) => (Some(title), field("TITLE")),
+        None => (field("TITLE"), None),
+    };
+
+    let preview = seconds("SAMPLESTART").map(|start| Preview {
+        start_seconds: start,
+        length_seconds: seconds("SAMPLELENGTH"),
+    });
 
+    // Songs are distributed in packs of song directories. Packs
+    // have no web page, so the pack is identified by the album.
+    let album = directory.parent().and_then(Path::file_name)
+        .map(|pack| pack.to_string_lossy().to_string());
+    let origin = Some(Origin { name: STEPMANIA_ORIGIN.to_string(), link: None });
+
+    let image = |key: &str| fields.get(key).map(|image| directory.join(image));
     Some(Metadata {
-        artist: find_regex_match(r"#ARTIST:([^;]+);", string),
-        title: find_regex_match(r"#TITLE:([^;]+);", string),
-        album: None,
-        origin: None,
+        artist,
+        title,
+        album,
+        origin,
+        artist_unicode,
+        title_unicode,
+        creator: field("CREDIT"),
+        preview,
+        subtitle: field("SUBTITLE"),
+        genre: field("GENRE"),
+        banner: image("BANNER"),
+        background: image("BACKGROUND"),
+        bpm: fields.get("BPMS").and_then(|bpms| stepmania_bpm(bpms)),
+        ..Metadata::default()
     })
 }
 
-/// Audio metadata tags.
-fn file_tags(path: &Path) -> Option<Metadata> {
+/// The range of a `beat=bpm,...` list of BPM changes.
+fn stepmania_bpm(bpms: &str) -> Option<Bpm> {
+    let bpms: Vec<f64> = bpms.split(',')
+        .filter_map(|change| change.split_once('='))
+        .filter_map(|(_, bpm)| bpm.trim().parse().ok())
+        .collect();
+
+    let min = bpms.iter().copied().reduce(f64::min)?;
+    let max = bpms.iter().copied().reduce(f64::max)?;
+    Some(Bpm { min, max })
+}
+
+/// https://clonehero.net
+fn clone_hero(path: &Path) -> Option<Metadata> {
+    let string = &read_file_string(&path.parent()?.join("song.ini"))?;
+    let fields = song_ini_fields(string);
+    let field = |key: &str| fields.get(key).map(|value| value.to_string());
+
+    // Songs without a preview have a negative preview time.
+    let preview = fields.get("preview_start_time")
+        .and_then(|time| time.parse::<u64>().ok())
+        .map(|time| Preview { start_seconds: time as f64 / 1000.0, length_seconds: None });
+
+    Some(Metadata {
+        artist: field("artist"),
+        title: field("name"),
+        album: field("album"),
+        // Older songs name the charter after the FoFiX frets field.
+        creator: field("charter").or_else(|| field("frets")),
+        preview,
+        genre: field("genre"),
+        year: fields.get("year").and_then(|year| find_regex_match(r"(\d{4})", year))
+            .and_then(|year| year.parse().ok()),
+        ..Metadata::default()
+    })
+}
+
+/// The non-empty `key = value` fields of the `[song]` section of a
+/// Clone Hero or Phase Shift `song.ini`, with lowercase keys.
+fn song_ini_fields(string: &str) -> HashMap<String, &str> {
+    let mut fields = HashMap::new();
+    let mut song = false;
+    for line in string.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            song = name.trim().eq_ignore_ascii_case("song");
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim();
+            if song && !value.is_empty() {
+                fields.insert(key.trim().to_lowercase(), value);
+            }
+        }
+    }
+
+    fields
+}
+
+#[derive(Deserialize)]
+struct BeatSaberInfo {
+    #[serde(rename = "_songName")]
+    song_name: Option<String>,
+    #[serde(rename = "_songSubName")]
+    song_sub_name: Option<String>,
+    #[serde(rename = "_songAuthorName")]
+    song_author_name: Option<String>,
+    #[serde(rename = "_levelAuthorName")]
+    level_author_name: Option<String>,
+    #[serde(rename = "_previewStartTime")]
+    preview_start_time: Option<f64>,
+    #[serde(rename = "_previewDuration")]
+    preview_duration: Option<f64>,
+    #[serde(rename = "_songFilename")]
+    song_filename: Option<String>,
+}
+
+/// https://beatsaver.com
+fn beat_saber(path: &Path) -> Option<Metadata> {
+    let directory = path.parent()?;
+    let info = ["Info.dat", "info.dat"].iter()
+        .find_map(|name| read_file_string(&directory.join(name)))?;
+    let info: BeatSaberInfo = serde_json::from_str(&info).ok()?;
+
+    // Levels only have a single song.
+    let name = path.file_name()?.to_string_lossy();
+    if !info.song_filename?.eq_ignore_ascii_case(&name) {
+        return None;
+    }
+
+    // Downloaded levels are named after their BeatSaver key.
+    let folder = directory.file_name()?.to_string_lossy();
+    let origin = find_regex_match(r"^([0-9a-fA-F]+) \(", &folder).map(|key| Origin {
+        name: "BeatSaver Map".to_string(),
+        link: Some(format!("https://beatsaver.com/maps/{}", key.to_lowercase())),
+    });
+
+    let preview = info.preview_start_time.map(|start| Preview {
+        start_seconds: start,
+        length_seconds: info.preview_duration,
+    });
+
+    let field = |value: Option<String>| value.filter(|value| !value.trim().is_empty());
+    Some(Metadata {
+        artist: field(info.song_author_name),
+        title: field(info.song_name),
+        origin,
+        creator: field(info.level_author_name),
+        preview,
+        subtitle: field(info.song_sub_name),
+        ..Metadata::default()
+    })
+}
+
+/// The audio file name of a Quaver map.
+fn quaver_audio(string: &str) -> Option<String> {
+    quaver_fields(string).remove("AudioFile")
+}
+
+/// The non-empty top level `Key: value` fields of a Quaver map.
+fn quaver_fields(string: &str) -> HashMap<&str, String> {
+    let mut fields = HashMap::new();
+    for line in string.lines() {
+        // Nested fields are indented or list items.
+        if line.starts_with(char::is_whitespace) || line.starts_with('-') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            let value = match value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')) {
+                Some(value) => value.replace("''", "'"),
+                None => value.trim_matches('"').to_string(),
+            };
+
+            if !value.is_empty() {
+                fields.insert(key.trim(), value);
+            }
+        }
+    }
+
+    fields
+}
+
+/// https://quavergame.com
+fn quaver(path: &Path) -> Option<Metadata> {
+    let (_, string) = &find_chart(path, &["qua"], quaver_audio)?;
+    let mut fields = quaver_fields(string);
+
+    // Unsubmitted maps have negative identifiers.
+    let mut id = |key: &str| fields.remove(key).filter(|value| value.parse::<u32>().is_ok());
+    let chart_id = id("MapId");
+    let origin = id("MapSetId").map(|id| Origin {
+        name: "Quaver Mapset".to_string(),
+        link: Some(format!("https://quavergame.com/mapset/{}", id)),
+    });
+
+    let preview = fields.get("SongPreviewTime")
+        .and_then(|time| time.parse::<u64>().ok())
+        .map(|time| Preview { start_seconds: time as f64 / 1000.0, length_seconds: None });
+
+    let tags = fields.get("Tags").map(|tags| tags.split_whitespace().map(str::to_string).collect());
+    Some(Metadata {
+        artist: fields.remove("Artist"),
+        title: fields.remove("Title"),
+        // The media the song is from is the closest to an album.
+        album: fields.remove("Source"),
+        origin,
+        creator: fields.remove("Creator"),
+        version: fields.remove("DifficultyName"),
+        tags: tags.unwrap_or_default(),
+        chart_id,
+        preview,
+        ..Metadata::default()
+    })
+}
+
+/// The playback length of an audio file.
+pub fn find_duration(path: &Path) -> Option<Duration> {
+    let result = probe_file(path)?;
+    let stream = result.format.default_stream()?;
+    let frames = stream.codec_params.n_frames?;
+    let sample_rate = stream.codec_params.sample_rate?;
+    Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64))
+}
+
+fn probe_file(path: &Path) -> Option<ProbeResult> {
     let file = File::open(path).ok()?;
     let mss = MediaSourceStream::new(Box::new(file), Default::default());
 
@@ -122,9 +514,14 @@ fn file_tags(path: &Path) -> Option<Metadata> {
         }
     }
 
-    // Get latest metadata revision.
     let probe = symphonia::default::get_probe();
-    let result = probe.format(&hint, mss, &Default::default(), &Default::default()).ok()?;
+    probe.format(&hint, mss, &Default::default(), &Default::default()).ok()
+}
+
+/// Audio metadata tags.
+fn file_tags(path: &Path) -> Option<Metadata> {
+    // Get latest metadata revision.
+    let result = probe_file(path)?;
     let metadata = result.metadata.current()?;
 
     // Search for tag.
@@ -155,6 +552,181 @@ fn file_tags(path: &Path) -> Option<Metadata> {
         artist,
         title: Some(title),
         album,
-        origin: None,
+        ..Metadata::default()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::testing::TempDirectory;
+
+    use super::*;
+
+    /// Write files to a new directory.
+    fn directory(root: &Path, name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = root.join(name);
+        fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            fs::write(directory.join(file), contents).unwrap();
+        }
+
+        directory
+    }
+
+    #[test]
+    fn osu_header() {
+        let sections = osu_sections("osu file format v14
+
+            [General]
+            AudioFilename: audio.mp3
+            PreviewTime: 45000
+
+            [Metadata]
+            Title:Hoshi
+            TitleUnicode:星
+            Source:
+            Tags:a b:c
+
+            [TimingPoints]
+            0,400,4,2,0,50,1,0
+        ");
+
+        assert_eq!(sections["General"]["AudioFilename"], "audio.mp3");
+        assert_eq!(sections["Metadata"]["TitleUnicode"], "星");
+        assert_eq!(sections["Metadata"]["Tags"], "a b:c");
+        assert_eq!(sections["Metadata"].get("Source"), None);
+        assert!(!sections.contains_key("TimingPoints"));
+        assert_eq!(osu_audio("[General]\nAudioFilename: a.ogg"), Some("a.ogg".to_string()));
+    }
+
+    #[test]
+    fn osu_beatmap() {
+        let root = TempDirectory::new("osu_beatmap");
+        let beatmap = directory(&root, "123 Kana - Hoshi", &[("Kana - Hoshi (me) [Hard].osu", "
+            [General]
+            AudioFilename: audio.mp3
+            PreviewTime: -1
+
+            [Metadata]
+            Title:Hoshi
+            TitleUnicode:星
+            Artist:Kana
+            Creator:me
+            Version:Hard
+            Source:Some Anime
+            BeatmapID:456
+            BeatmapSetID:-1
+        ")]);
+
+        let metadata = osu(&beatmap.join("audio.mp3")).unwrap();
+        assert_eq!(metadata.title(false), Some("Hoshi"));
+        assert_eq!(metadata.title(true), Some("星"));
+        assert_eq!(metadata.artist(true), Some("Kana"));
+        assert_eq!(metadata.album.as_deref(), Some("Some Anime"));
+        assert_eq!(metadata.version.as_deref(), Some("Hard"));
+        assert_eq!(metadata.chart_id.as_deref(), Some("456"));
+        assert_eq!(metadata.origin.unwrap().link.as_deref(), Some("https://osu.ppy.sh/beatmapsets/123"));
+        assert!(metadata.preview.is_none());
+    }
+
+    #[test]
+    fn stepmania_header() {
+        let fields = stepmania_fields("
+            #TITLE:星;
+            #TITLETRANSLIT:Hoshi;
+            #SUBTITLE:;
+            #BPMS:0.000=150.000
+            ,64.000=75.500;
+            #NOTEDATA:;
+            #CREDIT:Chart;
+        ");
+
+        assert_eq!(fields.get("TITLE"), Some(&"星"));
+        assert_eq!(fields.get("TITLETRANSLIT"), Some(&"Hoshi"));
+        assert_eq!(fields.get("SUBTITLE"), None);
+        assert_eq!(fields.get("CREDIT"), None);
+
+        let bpm = stepmania_bpm(fields["BPMS"]).unwrap();
+        assert_eq!((bpm.min, bpm.max), (75.5, 150.0));
+        assert!(stepmania_bpm("").is_none());
+    }
+
+    #[test]
+    fn stepmania_prefers_ssc() {
+        let root = TempDirectory::new("stepmania_prefers_ssc");
+        let pack = directory(&root, "stepmania", &[]);
+        let song = pack.join("Song");
+        fs::create_dir_all(&song).unwrap();
+        fs::write(song.join("a.sm"), "#TITLE:Old;#MUSIC:song.ogg;").unwrap();
+        fs::write(song.join("b.ssc"), "#TITLE:星;#TITLETRANSLIT:Hoshi;#ARTIST:Kana;
+            #MUSIC:song.ogg;#SAMPLESTART:30.5;#SAMPLELENGTH:12;").unwrap();
+
+        let metadata = stepmania(&song.join("song.ogg")).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Hoshi"));
+        assert_eq!(metadata.title(true), Some("星"));
+        assert_eq!(metadata.artist(true), Some("Kana"));
+        assert_eq!(metadata.album.as_deref(), Some("stepmania"));
+        assert!(metadata.origin.unwrap().link.is_none());
+
+        let preview = metadata.preview.unwrap();
+        assert_eq!((preview.start_seconds, preview.length_seconds), (30.5, Some(12.0)));
+    }
+
+    #[test]
+    fn beat_saber_info() {
+        let root = TempDirectory::new("beat_saber_info");
+        let level = directory(&root, "1a2B (Hoshi - me)", &[("Info.dat", r#"{
+            "_songName": "Hoshi",
+            "_songSubName": " ",
+            "_songAuthorName": "Kana",
+            "_levelAuthorName": "me",
+            "_previewStartTime": 12.5,
+            "_previewDuration": 10,
+            "_songFilename": "song.egg"
+        }"#)]);
+
+        let metadata = beat_saber(&level.join("Song.egg")).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Hoshi"));
+        assert_eq!(metadata.artist.as_deref(), Some("Kana"));
+        assert_eq!(metadata.creator.as_deref(), Some("me"));
+        assert_eq!(metadata.subtitle, None);
+        assert_eq!(metadata.origin.unwrap().link.as_deref(), Some("https://beatsaver.com/maps/1a2b"));
+
+        let preview = metadata.preview.unwrap();
+        assert_eq!((preview.start_seconds, preview.length_seconds), (12.5, Some(10.0)));
+        assert!(beat_saber(&level.join("other.egg")).is_none());
+    }
+
+    #[test]
+    fn quaver_map() {
+        let root = TempDirectory::new("quaver_map");
+        let map = directory(&root, "quaver", &[("1.qua", "
+AudioFile: audio.mp3
+SongPreviewTime: 45000
+MapId: -1
+MapSetId: 42
+Title: 'It''s'
+Artist: \"Kana\"
+Source: Some Anime
+Tags: a  b
+Creator: me
+DifficultyName: Hard
+EditorLayers: []
+TimingPoints:
+- StartTime: 0
+  Bpm: 150
+")]);
+
+        let metadata = quaver(&map.join("audio.mp3")).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("It's"));
+        assert_eq!(metadata.artist.as_deref(), Some("Kana"));
+        assert_eq!(metadata.album.as_deref(), Some("Some Anime"));
+        assert_eq!(metadata.version.as_deref(), Some("Hard"));
+        assert_eq!(metadata.tags, ["a", "b"]);
+        assert_eq!(metadata.chart_id, None);
+        assert_eq!(metadata.origin.unwrap().link.as_deref(), Some("https://quavergame.com/mapset/42"));
+        assert_eq!(metadata.preview.unwrap().start_seconds, 45.0);
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::osu::reader::Reader;
+use crate::osu::writer::Writer;
+
+/// The version written to new collection files.
+const VERSION: i32 = 20250107;
+
+/// A named list of beatmaps made in game.
+#[derive(Debug)]
+pub struct Collection {
+    pub name: String,
+    // The MD5 hashes of the beatmap files.
+    pub hashes: Vec<String>,
+}
+
+/// Read the collections in a `collection.db` file.
+pub fn read_collections(path: &Path) -> crate::Result<Vec<Collection>> {
+    Ok(read_file(path)?.1)
+}
+
+/// Add a collection to a `collection.db` file, keeping the other collections.
+/// The beatmaps of an existing collection with the same name are kept unless
+/// replaced. The previous file is backed up to `collection.db.bak`.
+pub fn write_collection(path: &Path, collection: Collection, replace: bool) -> crate::Result<()> {
+    let (version, mut collections) = match path.exists() {
+        false => (VERSION, Vec::new()),
+        true => {
+            let file = read_file(path)?;
+            let backup = path.with_extension("db.bak");
+            fs::copy(path, &backup)?;
+            eprintln!("Backed up collections to: {}", backup.display());
+            file
+        }
+    };
+
+    match collections.iter_mut().find(|existing| existing.name == collection.name) {
+        Some(existing) if replace => *existing = collection,
+        Some(existing) => {
+            for hash in collection.hashes {
+                if !existing.hashes.contains(&hash) {
+                    existing.hashes.push(hash);
+                }
+            }
+        }
+        None => collections.push(collection),
+    }
+
+    // Replace the file only once it is completely written.
+    let temporary = path.with_extension("db.tmp");
+    let mut writer = Writer::new(BufWriter::new(File::create(&temporary)?));
+    writer.i32(version)?;
+    writer.count(collections.len())?;
+    for collection in &collections {
+        writer.string(&collection.name)?;
+        writer.count(collection.hashes.len())?;
+        for hash in &collection.hashes {
+            writer.string(hash)?;
+        }
+    }
+
+    writer.into_inner().flush()?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+fn read_file(path: &Path) -> crate::Result<(i32, Vec<Collection>)> {
+    let mut reader = Reader::new(BufReader::new(File::open(path)?));
+    let version = reader.i32()?;
+    let count = reader.count()?;
+    let collections = (0..count).map(|_| {
+        let name = reader.string()?;
+        let count = reader.count()?;
+        let hashes = (0..count).map(|_| reader.string()).collect::<io::Result<_>>()?;
+        Ok(Collection { name, hashes })
+    }).collect::<io::Result<_>>()?;
+    Ok((version, collections))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDirectory;
+
+    use super::*;
+
+    fn collection(name: &str, hashes: &[&str]) -> Collection {
+        let hashes = hashes.iter().map(|hash| hash.to_string()).collect();
+        Collection { name: name.to_string(), hashes }
+    }
+
+    #[test]
+    fn write_read() {
+        let directory = TempDirectory::new("write_read");
+        let path = directory.join("collection.db");
+
+        write_collection(&path, collection("お気に入り", &["a", "b"]), false).unwrap();
+        write_collection(&path, collection("other", &[]), false).unwrap();
+        write_collection(&path, collection("お気に入り", &["b", "c"]), false).unwrap();
+        let (version, collections) = read_file(&path).unwrap();
+        assert_eq!(version, VERSION);
+        assert_eq!(collections.len(), 2);
+        assert_eq!(collections[0].name, "お気に入り");
+        assert_eq!(collections[0].hashes, ["a", "b", "c"]);
+        assert!(collections[1].hashes.is_empty());
+        assert!(path.with_extension("db.bak").is_file());
+
+        write_collection(&path, collection("お気に入り", &["d"]), true).unwrap();
+        let collections = read_collections(&path).unwrap();
+        assert_eq!(collections[0].hashes, ["d"]);
+        assert_eq!(collections[1].name, "other");
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::metadata::{Metadata, Origin, OSU_ORIGIN, Preview};
+use crate::osu::reader::Reader;
+
+// Versions of `osu!.db` that changed the format.
+const VERSION_FLOAT_DIFFICULTY: i32 = 20140609;
+const VERSION_NO_ENTRY_SIZE: i32 = 20191106;
+const VERSION_FLOAT_STAR_RATING: i32 = 20250107;
+
+/// Beatmaps by folder and lowercase audio file name.
+type Index = HashMap<(String, String), Beatmap>;
+
+/// The beatmaps of each `osu!.db` file read by this process.
+static INDEXES: Mutex<Vec<(PathBuf, Arc<Index>)>> = Mutex::new(Vec::new());
+
+/// A beatmap difficulty in `osu!.db`.
+#[derive(Debug, Serialize)]
+pub struct Beatmap {
+    pub artist: String,
+    pub artist_unicode: String,
+    pub title: String,
+    pub title_unicode: String,
+    pub creator: String,
+    // The difficulty name.
+    pub version: String,
+    // The audio file name within the beatmap folder.
+    pub audio_file: String,
+    pub md5: String,
+    // The beatmap file name within the beatmap folder.
+    pub osu_file: String,
+    // Milliseconds into the audio to start previews from.
+    pub preview_time: i32,
+    pub beatmap_id: i32,
+    pub beatmap_set_id: i32,
+    // The media the song is from.
+    pub source: String,
+    pub tags: String,
+    // The beatmap folder within the `Songs` directory.
+    pub folder: String,
+}
+
+impl Beatmap {
+    pub fn audio_path(&self, directory: &Path) -> PathBuf {
+        directory.join("Songs").join(&self.folder).join(&self.audio_file)
+    }
+
+    /// The metadata of the song, as read from the beatmap file.
+    pub fn metadata(&self) -> Metadata {
+        let text = |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+
+        // Unsubmitted beatmaps have negative identifiers.
+        let id = |id: i32| (id > 0).then(|| id.to_string());
+        let set = id(self.beatmap_set_id).or_else(|| {
+            let prefix = self.folder.split(' ').next().unwrap();
+            prefix.parse::<u32>().ok().map(|id| id.to_string())
+        });
+
+        let origin = set.map(|set| Origin {
+            name: OSU_ORIGIN.to_string(),
+            link: Some(format!("https://osu.ppy.sh/beatmapsets/{}", set)),
+        });
+
+        // Beatmaps without a preview have a negative preview time.
+        let preview = (self.preview_time >= 0).then(|| Preview {
+            start_seconds: self.preview_time as f64 / 1000.0,
+            length_seconds: None,
+        });
+
+        Metadata {
+            artist: text(&self.artist),
+            title: text(&self.title),
+            // The media the song is from is the closest to an album.
+            album: text(&self.source),
+            origin,
+            artist_unicode: text(&self.artist_unicode),
+            title_unicode: text(&self.title_unicode),
+            creator: text(&self.creator),
+            version: text(&self.version),
+            tags: self.tags.split_whitespace().map(str::to_string).collect(),
+            chart_id: id(self.beatmap_id),
+            preview,
+            ..Metadata::default()
+        }
+    }
+}
+
+/// The metadata of an audio file in the `Songs` folder of an osu! installation,
+/// if `osu!.db` has a beatmap using it. The database is only read once.
+pub fn beatmap_metadata(audio: &Path) -> Option<Metadata> {
+    let folder = audio.parent()?;
+    let songs = folder.parent()?;
+    if songs.file_name()? != "Songs" {
+        return None;
+    }
+
+    let index = index(&songs.parent()?.join("osu!.db"));
+    let folder = folder.file_name()?.to_string_lossy().to_string();
+    let audio = audio.file_name()?.to_string_lossy().to_lowercase();
+    index.get(&(folder, audio)).map(Beatmap::metadata)
+}
+
+fn index(path: &Path) -> Arc<Index> {
+    let mut indexes = INDEXES.lock().unwrap();
+    if let Some((_, index)) = indexes.iter().find(|(other, _)| other == path) {
+        return index.clone();
+    }
+
+    // Installations without a readable database fall back to reading beatmap files.
+    let mut index = Index::new();
+    for beatmap in read_beatmaps(path).unwrap_or_default() {
+        // Difficulties sharing audio are chosen like beatmap files are.
+        let key = (beatmap.folder.clone(), beatmap.audio_file.to_lowercase());
+        match index.get(&key) {
+            Some(existing) if existing.osu_file <= beatmap.osu_file => (),
+            _ => {
+                index.insert(key, beatmap);
+            }
+        }
+    }
+
+    let index = Arc::new(index);
+    indexes.push((path.to_path_buf(), index.clone()));
+    index
+}
+
+/// Read the beatmaps in an `osu!.db` file.
+pub fn read_beatmaps(path: &Path) -> crate::Result<Vec<Beatmap>> {
+    let mut reader = Reader::new(BufReader::new(File::open(path)?));
+    let version = reader.i32()?;
+    reader.i32()?; // Folder count.
+    reader.bool()?; // Account unlocked.
+    reader.u64()?; // Unlock date.
+    reader.string()?; // Player name.
+
+    let count = reader.count()?;
+    let beatmaps = (0..count).map(|_| read_beatmap(&mut reader, version));
+    Ok(beatmaps.collect::<io::Result<_>>()?)
+}
+
+fn read_beatmap(reader: &mut Reader<impl Read>, version: i32) -> io::Result<Beatmap> {
+    if version < VERSION_NO_ENTRY_SIZE {
+        reader.i32()?; // Entry size.
+    }
+
+    let artist = reader.string()?;
+    let artist_unicode = reader.string()?;
+    let title = reader.string()?;
+    let title_unicode = reader.string()?;
+    let creator = reader.string()?;
+    let beatmap_version = reader.string()?;
+    let audio_file = reader.string()?;
+    let md5 = reader.string()?;
+    let osu_file = reader.string()?;
+
+    // Ranked status, object counts and modification time.
+    reader.skip(1 + 2 * 3 + 8)?;
+    // Approach rate, circle size, drain rate and overall difficulty.
+    reader.skip(match version < VERSION_FLOAT_DIFFICULTY {
+        true => 4,
+        false => 4 * 4,
+    })?;
+
+    reader.skip(8)?; // Slider velocity.
+    if version >= VERSION_FLOAT_DIFFICULTY {
+        // Star ratings of each mode by mod combination.
+        let size = match version < VERSION_FLOAT_STAR_RATING {
+            true => 1 + 4 + 1 + 8,
+            false => 1 + 4 + 1 + 4,
+        };
+
+        for _ in 0..4 {
+            let count = reader.count()?;
+            reader.skip(count * size)?;
+        }
+    }
+
+    reader.skip(4 + 4)?; // Drain and total time.
+    let preview_time = reader.i32()?;
+    let timing_points = reader.count()?;
+    reader.skip(timing_points * (8 + 8 + 1))?;
+    let beatmap_id = reader.i32()?;
+    let beatmap_set_id = reader.i32()?;
+
+    // Thread, grades, local offset, stack leniency and mode.
+    reader.skip(4 + 4 + 2 + 4 + 1)?;
+    let source = reader.string()?;
+    let tags = reader.string()?;
+    reader.skip(2)?; // Online offset.
+    reader.string()?; // Title font.
+    reader.skip(1 + 8 + 1)?; // Unplayed, last played and osz2.
+    let folder = reader.string()?;
+
+    // Last online check and skin, sound, storyboard and video overrides.
+    reader.skip(8 + 5)?;
+    if version < VERSION_FLOAT_DIFFICULTY {
+        reader.skip(2)?;
+    }
+
+    reader.skip(4 + 1)?; // Modification time and mania scroll speed.
+    Ok(Beatmap {
+        artist,
+        artist_unicode,
+        title,
+        title_unicode,
+        creator,
+        version: beatmap_version,
+        audio_file,
+        md5,
+        osu_file,
+        preview_time,
+        beatmap_id,
+        beatmap_set_id,
+        source,
+        tags,
+        folder,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::osu::writer::Writer;
+    use crate::testing::TempDirectory;
+
+    use super::*;
+
+    fn string(bytes: &mut Vec<u8>, string: &str) {
+        Writer::new(bytes).string(string).unwrap();
+    }
+
+    fn i32(bytes: &mut Vec<u8>, value: i32) {
+        bytes.extend(value.to_le_bytes());
+    }
+
+    /// Fields that are skipped are filled with `0xff` so misread sizes are noticed.
+    fn skip(bytes: &mut Vec<u8>, count: usize) {
+        bytes.resize(bytes.len() + count, 0xff);
+    }
+
+    fn beatmap(bytes: &mut Vec<u8>, version: i32, id: i32) {
+        if version < 20191106 {
+            i32(bytes, 0);
+        }
+
+        for field in ["Kana", "カナ", "Hoshi", "星", "me", "Hard", "audio.mp3"] {
+            string(bytes, field);
+        }
+
+        string(bytes, &format!("hash{}", id));
+        string(bytes, "Kana - Hoshi (me) [Hard].osu");
+        skip(bytes, 15);
+        skip(bytes, if version < 20140609 { 4 } else { 16 });
+        skip(bytes, 8);
+        if version >= 20140609 {
+            let pair = if version < 20250107 { 14 } else { 10 };
+            for mode in 0..4 {
+                i32(bytes, mode);
+                skip(bytes, mode as usize * pair);
+            }
+        }
+
+        skip(bytes, 8);
+        i32(bytes, 45000);
+        i32(bytes, 2);
+        skip(bytes, 2 * 17);
+        i32(bytes, id);
+        i32(bytes, 123);
+        skip(bytes, 15);
+        string(bytes, "Some Anime");
+        string(bytes, "a b c");
+        skip(bytes, 2);
+        string(bytes, "");
+        skip(bytes, 10);
+        string(bytes, "123 Kana - Hoshi");
+        skip(bytes, 13);
+        if version < 20140609 {
+            skip(bytes, 2);
+        }
+
+        skip(bytes, 5);
+    }
+
+    /// A database of two difficulties of the same beatmap set.
+    fn database(version: i32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        i32(&mut bytes, version);
+        i32(&mut bytes, 1);
+        skip(&mut bytes, 1 + 8);
+        string(&mut bytes, "player");
+        i32(&mut bytes, 2);
+        beatmap(&mut bytes, version, 1);
+        beatmap(&mut bytes, version, 2);
+        bytes
+    }
+
+    #[test]
+    fn read_versions() {
+        let directory = TempDirectory::new("read_versions");
+
+        for version in [20140000, 20150000, 20191106, 20250107] {
+            let path = directory.join(format!("{}.db", version));
+            fs::write(&path, database(version)).unwrap();
+            let beatmaps = read_beatmaps(&path).unwrap();
+            assert_eq!(beatmaps.len(), 2, "version {}", version);
+
+            let beatmap = &beatmaps[1];
+            assert_eq!(beatmap.title_unicode, "星");
+            assert_eq!(beatmap.md5, "hash2");
+            assert_eq!(beatmap.preview_time, 45000);
+            assert_eq!((beatmap.beatmap_id, beatmap.beatmap_set_id), (2, 123));
+            assert_eq!(beatmap.tags, "a b c");
+            assert_eq!(
+                beatmap.audio_path(Path::new("osu")),
+                Path::new("osu/Songs/123 Kana - Hoshi/audio.mp3"),
+            );
+        }
+    }
+
+    #[test]
+    fn installed_metadata() {
+        let directory = TempDirectory::new("installed_metadata");
+        let folder = directory.join("Songs").join("123 Kana - Hoshi");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(directory.join("osu!.db"), database(20250107)).unwrap();
+
+        // Beatmap files are not needed for installed beatmaps.
+        let metadata = crate::metadata::find_metadata(&folder.join("Audio.mp3"));
+        assert_eq!(metadata.title(true), Some("星"));
+        assert_eq!(metadata.artist(false), Some("Kana"));
+        assert_eq!(metadata.album.as_deref(), Some("Some Anime"));
+        assert_eq!(metadata.tags, ["a", "b", "c"]);
+        assert_eq!(metadata.chart_id.as_deref(), Some("1"));
+        assert_eq!(metadata.preview.unwrap().start_seconds, 45.0);
+        assert_eq!(metadata.origin.unwrap().link.as_deref(), Some("https://osu.ppy.sh/beatmapsets/123"));
+        assert!(beatmap_metadata(&folder.join("other.mp3")).is_none());
+    }
+}
@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::library;
+use crate::osu::{directory, read_beatmaps};
+
+/// A beatmap set or beatmap on the osu! website.
+#[derive(Debug)]
+pub enum Link {
+    Set(u32),
+    // A single difficulty of a set.
+    Beatmap(u32),
+}
+
+/// Parse `osu:<set>` or a beatmap set or beatmap URL.
+pub fn parse_link(input: &str) -> Option<Link> {
+    if let Some(set) = input.strip_prefix("osu:") {
+        return set.parse().ok().map(Link::Set);
+    }
+
+    let pattern = Regex::new(r"^https?://osu\.ppy\.sh/(beatmapsets|s|beatmaps|b)/(\d+)").unwrap();
+    let captures = pattern.captures(input)?;
+    let id = captures[2].parse().ok()?;
+    match &captures[1] {
+        "beatmapsets" | "s" => Some(Link::Set(id)),
+        _ => Some(Link::Beatmap(id)),
+    }
+}
+
+/// The local audio files of a link. Sets are found in the library index
+/// or by their folder name in the osu! and library `Songs` directories.
+pub fn link_songs(config: &Config, link: &Link) -> crate::Result<Vec<PathBuf>> {
+    let mut paths = match link {
+        Link::Set(set) => {
+            let origin = format!("https://osu.ppy.sh/beatmapsets/{}", set);
+            let mut paths = library::find_origin(&origin)?;
+            paths.retain(|path| path.is_file());
+            if paths.is_empty() {
+                paths = set_folder_songs(config, *set);
+            }
+
+            paths
+        }
+        Link::Beatmap(id) => {
+            // Beatmaps are only found through the osu! database.
+            let directory = directory(config)?;
+            read_beatmaps(&directory.join("osu!.db"))?.into_iter()
+                .filter(|beatmap| beatmap.beatmap_id == *id as i32)
+                .map(|beatmap| beatmap.audio_path(&directory))
+                .filter(|path| path.is_file())
+                .collect()
+        }
+    };
+
+    paths.sort();
+    paths.dedup();
+    match paths.is_empty() {
+        false => Ok(paths),
+        true => Err(format!("Beatmap is not installed: {:?}", link).into()),
+    }
+}
+
+/// Audio files in folders named after a beatmap set (e.g. `123456 Artist - Title`).
+fn set_folder_songs(config: &Config, set: u32) -> Vec<PathBuf> {
+    let mut roots: Vec<_> = directory(config).ok()
+        .map(|directory| directory.join("Songs")).into_iter().collect();
+    for root in &config.library_roots {
+        roots.push(root.join("Songs"));
+        roots.push(root.clone());
+    }
+
+    let prefix = format!("{} ", set);
+    let set = set.to_string();
+    roots.iter().filter_map(|root| root.read_dir().ok())
+        .flat_map(|entries| entries.flatten())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name == set || name.starts_with(&prefix)
+        })
+        .flat_map(|entry| library::songs(&entry.path()))
+        .collect()
+}
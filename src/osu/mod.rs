@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::metadata::osu_audio;
+
+pub use collection::*;
+pub use database::*;
+pub use link::*;
+
+mod collection;
+mod database;
+mod link;
+mod reader;
+mod writer;
+
+/// The osu! installation directory.
+pub fn directory(config: &Config) -> crate::Result<PathBuf> {
+    if let Some(directory) = &config.osu_directory {
+        return Ok(directory.clone());
+    }
+
+    // Default installation directory on Windows.
+    dirs::data_local_dir().map(|directory| directory.join("osu!"))
+        .filter(|directory| directory.is_dir())
+        .ok_or_else(|| "The osu! directory is not configured.".into())
+}
+
+/// The audio files of the beatmaps in a collection.
+pub fn collection_songs(config: &Config, name: &str) -> crate::Result<Vec<PathBuf>> {
+    let directory = directory(config)?;
+    let collection = read_collections(&directory.join("collection.db"))?
+        .into_iter().find(|collection| collection.name == name)
+        .ok_or_else(|| format!("Collection does not exist: {}", name))?;
+
+    let beatmaps: HashMap<_, _> = read_beatmaps(&directory.join("osu!.db"))?
+        .into_iter().map(|beatmap| (beatmap.md5.clone(), beatmap)).collect();
+
+    // Difficulties of a beatmap set usually share audio.
+    let mut paths = Vec::new();
+    for hash in &collection.hashes {
+        if let Some(beatmap) = beatmaps.get(hash) {
+            let path = beatmap.audio_path(&directory);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Print collections as tab separated lines of the name and number of beatmaps.
+pub fn print_collections(config: &Config, json: bool) -> crate::Result<()> {
+    let directory = directory(config)?;
+    let collections = read_collections(&directory.join("collection.db"))?;
+    if !json {
+        for collection in collections {
+            println!("{}\t{}", collection.name, collection.hashes.len());
+        }
+
+        return Ok(());
+    }
+
+    // Include the installed beatmaps of each collection.
+    let beatmaps: HashMap<_, _> = read_beatmaps(&directory.join("osu!.db"))?
+        .into_iter().map(|beatmap| (beatmap.md5.clone(), beatmap)).collect();
+    let collections: Vec<_> = collections.iter().map(|collection| {
+        let beatmaps: Vec<_> = collection.hashes.iter()
+            .filter_map(|hash| beatmaps.get(hash)).collect();
+        serde_json::json!({ "name": collection.name, "beatmaps": beatmaps })
+    }).collect();
+
+    println!("{}", serde_json::to_string_pretty(&collections)?);
+    Ok(())
+}
+
+/// Save songs as a collection, merging into any collection with the same name unless replaced.
+pub fn export_collection(
+    config: &Config,
+    name: &str,
+    paths: &[PathBuf],
+    replace: bool,
+) -> crate::Result<()> {
+    let mut hashes = Vec::new();
+    for path in paths {
+        let beatmaps = match beatmap_hashes(path) {
+            Ok(beatmaps) if path.is_file() => beatmaps,
+            _ => {
+                eprintln!("Skipping missing song: {}", path.display());
+                continue;
+            }
+        };
+
+        if beatmaps.is_empty() {
+            eprintln!("Skipping song without beatmaps: {}", path.display());
+        }
+
+        for hash in beatmaps {
+            if !hashes.contains(&hash) {
+                hashes.push(hash);
+            }
+        }
+    }
+
+    if hashes.is_empty() {
+        return Err("No songs have beatmaps.".into());
+    }
+
+    let count = hashes.len();
+    let path = directory(config)?.join("collection.db");
+    write_collection(&path, Collection { name: name.to_string(), hashes }, replace)?;
+    println!("Exported {} beatmaps to collection: {}", count, name);
+    Ok(())
+}
+
+/// The MD5 hashes of the beatmaps next to an audio file that use it.
+fn beatmap_hashes(audio: &Path) -> crate::Result<Vec<String>> {
+    let name = audio.file_name().unwrap().to_string_lossy();
+    let directory = audio.parent().unwrap();
+
+    let mut hashes = Vec::new();
+    for entry in directory.read_dir()?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("osu") {
+            continue;
+        }
+
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+
+        let string = String::from_utf8_lossy(&bytes);
+        if let Some(file) = osu_audio(&string) {
+            if file.eq_ignore_ascii_case(&name) {
+                hashes.push(format!("{:x}", md5::compute(&bytes)));
+            }
+        }
+    }
+
+    Ok(hashes)
+}
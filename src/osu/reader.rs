@@ -0,0 +1,78 @@
+use std::io;
+use std::io::{ErrorKind, Read};
+
+/// Reads the little endian values of osu! database files.
+pub struct Reader<R> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    /// Read a count prefix, treating negative counts as empty.
+    pub fn count(&mut self) -> io::Result<u64> {
+        Ok(self.i32()?.max(0) as u64)
+    }
+
+    pub fn skip(&mut self, count: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.inner).take(count), &mut io::sink())?;
+        match skipped == count {
+            true => Ok(()),
+            false => Err(ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    /// Read a string that is either empty or prefixed by its length.
+    pub fn string(&mut self) -> io::Result<String> {
+        match self.u8()? {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let mut bytes = vec![0; self.uleb128()? as usize];
+                self.inner.read_exact(&mut bytes)?;
+                String::from_utf8(bytes)
+                    .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+            }
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "Invalid string marker")),
+        }
+    }
+
+    fn uleb128(&mut self) -> io::Result<u64> {
+        let (mut value, mut shift) = (0, 0);
+        loop {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+
+            shift += 7;
+            if shift >= 64 {
+                return Err(io::Error::new(ErrorKind::InvalidData, "Invalid string length"));
+            }
+        }
+    }
+}
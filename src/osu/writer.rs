@@ -0,0 +1,47 @@
+use std::io;
+use std::io::Write;
+
+/// Writes the little endian values of osu! database files.
+pub struct Writer<W> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn i32(&mut self, value: i32) -> io::Result<()> {
+        self.inner.write_all(&value.to_le_bytes())
+    }
+
+    /// Write a count prefix.
+    pub fn count(&mut self, count: usize) -> io::Result<()> {
+        self.i32(count as i32)
+    }
+
+    pub fn string(&mut self, string: &str) -> io::Result<()> {
+        if string.is_empty() {
+            return self.inner.write_all(&[0x00]);
+        }
+
+        self.inner.write_all(&[0x0b])?;
+        self.uleb128(string.len() as u64)?;
+        self.inner.write_all(string.as_bytes())
+    }
+
+    fn uleb128(&mut self, mut value: u64) -> io::Result<()> {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            match value {
+                0 => return self.inner.write_all(&[byte]),
+                _ => self.inner.write_all(&[byte | 0x80])?,
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
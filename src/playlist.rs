@@ -0,0 +1,244 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use rusqlite::{OptionalExtension, params, params_from_iter};
+use rusqlite::types::Value;
+
+use crate::{database, library};
+use crate::library::Song;
+
+pub fn is_m3u(path: &Path) -> bool {
+    let extension = path.extension().and_then(OsStr::to_str);
+    matches!(extension, Some("m3u" | "m3u8"))
+}
+
+/// Read the songs of an M3U playlist. Relative
+/// paths are resolved from the playlist directory.
+pub fn read_m3u(path: &Path) -> crate::Result<Vec<PathBuf>> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let string = fs::read_to_string(path)?;
+    Ok(string.lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| directory.join(line))
+        .collect())
+}
+
+/// Fields of the library and listens that smart playlist queries can use.
+const FIELDS: &[(&str, &str)] = &[
+    ("artist", "library.artist"),
+    ("title", "library.title"),
+    ("album", "library.album"),
+    ("origin", "COALESCE(library.origin_name, '') || ' ' || COALESCE(library.origin_link, '')"),
+    ("path", "library.path"),
+    ("plays", "COALESCE(stats.plays, 0)"),
+    ("skips", "COALESCE(stats.skips, 0)"),
+    ("duration", "library.duration"),
+    // Seconds since the song was last played.
+    ("played", "strftime('%s', 'now') - COALESCE(stats.played, 0)"),
+];
+
+/// A smart playlist query translated to SQL.
+#[derive(Debug, Default)]
+struct Query {
+    conditions: Vec<String>,
+    values: Vec<Value>,
+    order: Vec<String>,
+    limit: Option<usize>,
+}
+
+pub fn create(name: &str, query: &str) -> crate::Result<()> {
+    parse(query)?;
+    let conn = database::connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO playlists (name, query) VALUES (?1, ?2)",
+        params![name, query],
+    )?;
+    Ok(())
+}
+
+/// Remove a smart playlist and return whether it existed.
+pub fn remove(name: &str) -> crate::Result<bool> {
+    let conn = database::connect()?;
+    let count = conn.execute("DELETE FROM playlists WHERE name = ?1", params![name])?;
+    Ok(count > 0)
+}
+
+/// The query of a smart playlist.
+pub fn find(name: &str) -> crate::Result<Option<String>> {
+    let conn = database::connect()?;
+    let result = conn.query_row(
+        "SELECT query FROM playlists WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    );
+
+    Ok(result.optional()?)
+}
+
+pub fn list() -> crate::Result<Vec<(String, String)>> {
+    let conn = database::connect()?;
+    let mut statement = conn.prepare("SELECT name, query FROM playlists ORDER BY name")?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Print smart playlists as tab separated lines.
+pub fn print() -> crate::Result<()> {
+    for (name, query) in list()? {
+        println!("{}\t{}", name, query);
+    }
+
+    Ok(())
+}
+
+/// The songs of a smart playlist, or `None` if it does not exist.
+pub fn smart_songs(name: &str) -> crate::Result<Option<Vec<Song>>> {
+    find(name)?.map(|query| evaluate(&query)).transpose()
+}
+
+/// Indexed songs matching a smart playlist query.
+pub fn evaluate(query: &str) -> crate::Result<Vec<Song>> {
+    let query = parse(query)?;
+    let conditions = match query.conditions.is_empty() {
+        false => query.conditions.join(" AND "),
+        true => "1".to_string(),
+    };
+
+    let order = match query.order.is_empty() {
+        false => query.order.join(", "),
+        true => "library.path".to_string(),
+    };
+
+    let conn = database::connect()?;
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM library
+        LEFT JOIN (
+            SELECT path, COUNT(*) AS plays, SUM(NOT completed) AS skips, MAX(timestamp) AS played
+            FROM listens
+            GROUP BY path
+        ) AS stats ON stats.path = library.path
+        WHERE {}
+        ORDER BY {}
+        LIMIT {}", library::COLUMNS, conditions, order,
+        query.limit.map_or(-1, |limit| limit as i64)))?;
+
+    let rows = statement.query_map(params_from_iter(query.values), library::song)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Translate a query such as `origin:osu plays>=5 played>7d sort:-plays limit:20`.
+/// Text fields match substrings with `:` or whole values with `=`.
+fn parse(query: &str) -> crate::Result<Query> {
+    let pattern = Regex::new(r"^([a-z]+)(>=|<=|:|=|>|<)(.+)$").unwrap();
+    let mut parsed = Query::default();
+    for term in terms(query) {
+        let captures = pattern.captures(&term)
+            .ok_or_else(|| format!("Invalid query term: {}", term))?;
+        let (field, operator, value) = (&captures[1], &captures[2], &captures[3]);
+        match (field, operator) {
+            ("sort", ":") => parsed.order.push(order(value)?),
+            ("limit", ":") => parsed.limit = Some(value.parse()?),
+            ("artist" | "title" | "album" | "origin" | "path", ":") => {
+                parsed.conditions.push(format!("{} LIKE ?", expression(field)?));
+                parsed.values.push(Value::Text(format!("%{}%", value)));
+            }
+            ("artist" | "title" | "album" | "origin" | "path", "=") => {
+                parsed.conditions.push(format!("{} = ? COLLATE NOCASE", expression(field)?));
+                parsed.values.push(Value::Text(value.to_string()));
+            }
+            ("plays" | "skips", operator) if operator != ":" => {
+                parsed.conditions.push(format!("{} {} ?", expression(field)?, operator));
+                parsed.values.push(Value::Integer(value.parse()?));
+            }
+            ("duration" | "played", operator) if operator != ":" => {
+                let seconds = crate::parse_age(value)?.as_secs();
+                parsed.conditions.push(format!("{} {} ?", expression(field)?, operator));
+                parsed.values.push(Value::Integer(seconds as i64));
+            }
+            _ => return Err(format!("Invalid query term: {}", term).into()),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Split a query on whitespace outside of double quotes.
+fn terms(query: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut term = String::new();
+    let mut quoted = false;
+    for character in query.chars() {
+        match character {
+            '"' => quoted = !quoted,
+            _ if character.is_whitespace() && !quoted => {
+                if !term.is_empty() {
+                    terms.push(std::mem::take(&mut term));
+                }
+            }
+            _ => term.push(character),
+        }
+    }
+
+    if !term.is_empty() {
+        terms.push(term);
+    }
+
+    terms
+}
+
+/// An `ORDER BY` term for a field, descending if prefixed with `-`.
+fn order(field: &str) -> crate::Result<String> {
+    let (field, direction) = match field.strip_prefix('-') {
+        Some(field) => (field, "DESC"),
+        None => (field, "ASC"),
+    };
+
+    match field {
+        "random" => Ok("random()".to_string()),
+        _ => Ok(format!("{} {}", expression(field)?, direction)),
+    }
+}
+
+fn expression(field: &str) -> crate::Result<&'static str> {
+    FIELDS.iter().find(|(name, _)| *name == field)
+        .map(|(_, expression)| *expression)
+        .ok_or_else(|| format!("Unknown query field: {}", field).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_terms() {
+        let query = r#"origin:osu artist="Kana Boon" plays>=5 played>7d sort:-plays sort:random limit:20"#;
+        let query = parse(query).unwrap();
+        assert_eq!(query.conditions, [
+            format!("{} LIKE ?", expression("origin").unwrap()),
+            "library.artist = ? COLLATE NOCASE".to_string(),
+            "COALESCE(stats.plays, 0) >= ?".to_string(),
+            format!("{} > ?", expression("played").unwrap()),
+        ]);
+
+        assert_eq!(query.values, [
+            Value::Text("%osu%".to_string()),
+            Value::Text("Kana Boon".to_string()),
+            Value::Integer(5),
+            Value::Integer(7 * 24 * 60 * 60),
+        ]);
+
+        assert_eq!(query.order, ["COALESCE(stats.plays, 0) DESC", "random()"]);
+        assert_eq!(query.limit, Some(20));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse("").unwrap().conditions.is_empty());
+        for query in ["camellia", "plays:5", "artist>a", "genre:rock", "sort:genre", "limit:-1", "plays>=x"] {
+            assert!(parse(query).is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::io::{self, BufRead, Write};
+
+/// Ask the user to pick one of several options.
+/// Returns `None` if nothing was chosen.
+pub fn choose(question: &str, options: &[String]) -> crate::Result<Option<usize>> {
+    let mut stderr = io::stderr();
+    writeln!(stderr, "{}", question)?;
+    for (index, option) in options.iter().enumerate() {
+        writeln!(stderr, "{:>3}. {}", index + 1, option)?;
+    }
+
+    write!(stderr, "Choice [1-{}]: ", options.len())?;
+    stderr.flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(match line.trim().parse::<usize>() {
+        Ok(choice) if (1..=options.len()).contains(&choice) => Some(choice - 1),
+        _ => None,
+    })
+}
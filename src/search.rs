@@ -0,0 +1,68 @@
+use rusqlite::params;
+
+use crate::{database, library};
+use crate::library::Song;
+
+/// Fields that terms can be restricted to with `field:term`.
+const FIELDS: &[&str] = &["artist", "title", "album", "origin", "path"];
+
+/// Songs in the library matching a query, best matches first.
+pub fn search(query: &str, limit: Option<usize>) -> crate::Result<Vec<Song>> {
+    let expression = match_expression(query);
+    if expression.is_empty() {
+        return Err("Search query is empty.".into());
+    }
+
+    // Matches in the artist and title rank above other fields.
+    let conn = database::connect()?;
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM library_search
+        JOIN library ON library.rowid = library_search.rowid
+        WHERE library_search MATCH ?1
+        ORDER BY bm25(library_search, 10.0, 10.0, 5.0, 2.0, 1.0)
+        LIMIT ?2", library::COLUMNS))?;
+
+    let limit = limit.map_or(-1, |limit| limit as i64);
+    let rows = statement.query_map(params![expression, limit], library::song)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Convert a query into an FTS5 expression. Every term matches
+/// as a prefix and may be restricted to a field (e.g. `origin:osu`).
+fn match_expression(query: &str) -> String {
+    let terms: Vec<_> = query.split_whitespace().filter_map(|term| {
+        let (field, term) = match term.split_once(':') {
+            Some((field, term)) if FIELDS.contains(&field) => (Some(field), term),
+            _ => (None, term),
+        };
+
+        let term = format!("\"{}\"*", term.replace('"', "\"\""));
+        match field {
+            _ if term == "\"\"*" => None,
+            Some(field) => Some(format!("{} : {}", field, term)),
+            None => Some(term),
+        }
+    }).collect();
+    terms.join(" ")
+}
+
+/// Print songs as tab separated lines of the path and song name.
+pub fn print(songs: &[Song], json: bool) -> crate::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(songs)?);
+        return Ok(());
+    }
+
+    for song in songs {
+        let metadata = &song.metadata;
+        let title = metadata.title.as_deref().unwrap_or("Unknown Title");
+        let name = match &metadata.artist {
+            Some(artist) => format!("{} - {}", artist, title),
+            None => title.to_string(),
+        };
+
+        println!("{}\t{}", song.path.display(), name);
+    }
+
+    Ok(())
+}
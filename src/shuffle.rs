@@ -0,0 +1,141 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rusqlite::params;
+
+use crate::{database, playlist};
+use crate::library::Song;
+use crate::metadata::find_metadata;
+
+/// The most listens excluded from shuffling as recent repeats.
+const RECENT_LISTENS: usize = 50;
+
+/// The time before the songs of a shuffle are loaded again to
+/// include changes to the library and their weights.
+const RELOAD: Duration = Duration::from_secs(10 * 60);
+
+/// Songs matching a smart playlist query or the name of a smart playlist (`@name`).
+/// An empty query matches the whole library.
+pub fn candidates(query: &str) -> crate::Result<Vec<Song>> {
+    match query.strip_prefix('@') {
+        None => playlist::evaluate(query),
+        Some(name) => playlist::smart_songs(name)?
+            .ok_or_else(|| format!("Smart playlist does not exist: {}", name).into()),
+    }
+}
+
+/// Songs chosen from by a shuffle with their weights.
+pub struct Shuffle {
+    query: String,
+    songs: Vec<Song>,
+    weights: Vec<f64>,
+    // The time the songs were loaded.
+    loaded: Instant,
+}
+
+impl Shuffle {
+    pub fn new(query: String) -> crate::Result<Shuffle> {
+        let (songs, weights) = load(&query)?;
+        Ok(Shuffle { query, songs, weights, loaded: Instant::now() })
+    }
+
+    /// Choose a random song to play after the upcoming songs. Songs that are often
+    /// skipped are less likely, and recent songs or songs sharing an artist or
+    /// origin with the previous song are avoided while there are alternatives.
+    pub fn choose(&mut self, upcoming: &[&Path]) -> crate::Result<Option<PathBuf>> {
+        if self.loaded.elapsed() >= RELOAD {
+            *self = Shuffle::new(std::mem::take(&mut self.query))?;
+        }
+
+        loop {
+            let index = match self.pick(upcoming)? {
+                Some(index) => index,
+                None => return Ok(None),
+            };
+
+            // Songs may have been removed since they were loaded.
+            match self.songs[index].path.is_file() {
+                true => return Ok(Some(self.songs[index].path.clone())),
+                false => {
+                    self.songs.swap_remove(index);
+                    self.weights.swap_remove(index);
+                }
+            }
+        }
+    }
+
+    fn pick(&self, upcoming: &[&Path]) -> crate::Result<Option<usize>> {
+        let conn = database::connect()?;
+        let mut statement = conn.prepare(
+            "SELECT path FROM listens
+            WHERE path IS NOT NULL
+            ORDER BY timestamp DESC
+            LIMIT ?1")?;
+        let limit = RECENT_LISTENS.min(self.songs.len() / 2);
+        let rows = statement.query_map(params![limit], |row| row.get(0))?;
+        let mut recent: HashSet<String> = rows.collect::<Result<_, _>>()?;
+        recent.extend(upcoming.iter().map(|path| path.to_string_lossy().to_string()));
+
+        let previous = upcoming.last().map(|path| find_metadata(path));
+        let similar = |song: &Song| match &previous {
+            None => false,
+            Some(previous) => {
+                let metadata = &song.metadata;
+                let artist = match (&metadata.artist, &previous.artist) {
+                    (Some(artist), Some(previous)) => artist.eq_ignore_ascii_case(previous),
+                    _ => false,
+                };
+
+                let origin = match (&metadata.origin, &previous.origin) {
+                    (Some(origin), Some(previous)) => origin.link.is_some() && origin.link == previous.link,
+                    _ => false,
+                };
+
+                artist || origin
+            }
+        };
+
+        let filters: [&dyn Fn(&Song) -> bool; 3] = [
+            &|song| !recent.contains(song.path.to_string_lossy().as_ref()) && !similar(song),
+            &|song| !recent.contains(song.path.to_string_lossy().as_ref()),
+            &|song| upcoming.last() != Some(&song.path.as_path()),
+        ];
+
+        for filter in filters {
+            let indices: Vec<_> = (0..self.songs.len())
+                .filter(|index| filter(&self.songs[*index])).collect();
+            let weights = indices.iter().map(|index| self.weights[*index]);
+            if let Ok(distribution) = WeightedIndex::new(weights) {
+                let index = distribution.sample(&mut rand::thread_rng());
+                return Ok(Some(indices[index]));
+            }
+        }
+
+        Ok((!self.songs.is_empty()).then_some(0))
+    }
+}
+
+/// The songs matching a query that exist and their weights.
+fn load(query: &str) -> crate::Result<(Vec<Song>, Vec<f64>)> {
+    let songs: Vec<_> = candidates(query)?.into_iter()
+        .filter(|song| song.path.is_file()).collect();
+
+    let conn = database::connect()?;
+    let mut statement = conn.prepare(
+        "SELECT path, COUNT(*), SUM(NOT completed) FROM listens
+        WHERE path IS NOT NULL
+        GROUP BY path")?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?;
+    let listens: HashMap<String, (u64, u64)> = rows.collect::<Result<_, _>>()?;
+
+    // The smoothed fraction of listens that were not skipped.
+    let weights = songs.iter().map(|song| {
+        let path = song.path.to_string_lossy();
+        let (count, skips) = listens.get(path.as_ref()).copied().unwrap_or((0, 0));
+        (count - skips + 1) as f64 / (count + 2) as f64
+    }).collect();
+
+    Ok((songs, weights))
+}
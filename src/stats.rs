@@ -0,0 +1,232 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, params};
+use serde::Serialize;
+
+use crate::database;
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    // The number of songs played.
+    pub listens: u64,
+    // The total time spent listening.
+    pub listened_seconds: u64,
+    pub tracks: Vec<Count>,
+    pub artists: Vec<Count>,
+    pub albums: Vec<Count>,
+    pub origins: Vec<Count>,
+    pub longest_streak: Option<Streak>,
+    // Consecutive days with listens up to today.
+    pub current_streak: u64,
+    // Seconds listened in each hour of the day.
+    pub hours: [u64; 24],
+}
+
+#[derive(Debug, Serialize)]
+pub struct Count {
+    pub name: String,
+    pub listens: u64,
+    pub listened_seconds: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Streak {
+    pub days: u64,
+    pub start: String,
+    pub end: String,
+}
+
+pub fn stats(since: Option<Duration>, limit: usize) -> crate::Result<Stats> {
+    let since = match since {
+        Some(since) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            now.saturating_sub(since).as_secs()
+        }
+        None => 0,
+    };
+
+    let conn = &database::connect()?;
+    let (listens, listened_seconds) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(listened), 0) FROM listens
+        WHERE timestamp >= ?1",
+        params![since],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let (longest_streak, current_streak) = streaks(conn, since)?;
+    Ok(Stats {
+        listens,
+        listened_seconds,
+        tracks: top(conn, "COALESCE(artist || ' - ', '') || title", since, limit)?,
+        artists: top(conn, "artist", since, limit)?,
+        albums: top(conn, "album", since, limit)?,
+        // Origins without a web page, such as StepMania packs, are told apart by album.
+        origins: top(conn, "COALESCE(origin_link, origin_name || COALESCE(': ' || album, ''))", since, limit)?,
+        longest_streak,
+        current_streak,
+        hours: hours(conn, since)?,
+    })
+}
+
+/// The paths of the most played songs from an origin.
+pub fn top_songs(origin_name: &str, limit: usize) -> crate::Result<Vec<PathBuf>> {
+    let conn = database::connect()?;
+    let mut statement = conn.prepare(
+        "SELECT path FROM listens
+        WHERE origin_name = ?1 AND path IS NOT NULL
+        GROUP BY path
+        ORDER BY COUNT(*) DESC, SUM(listened) DESC
+        LIMIT ?2")?;
+
+    let rows = statement.query_map(params![origin_name, limit], |row| row.get::<_, String>(0))?;
+    Ok(rows.map(|path| path.map(PathBuf::from)).collect::<Result<_, _>>()?)
+}
+
+/// The most played values of an expression over the listens table.
+fn top(conn: &Connection, expression: &str, since: u64, limit: usize) -> crate::Result<Vec<Count>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {0}, COUNT(*), SUM(listened) FROM listens
+        WHERE timestamp >= ?1 AND {0} IS NOT NULL
+        GROUP BY {0}
+        ORDER BY COUNT(*) DESC, SUM(listened) DESC
+        LIMIT ?2", expression))?;
+
+    let rows = statement.query_map(params![since, limit], |row| Ok(Count {
+        name: row.get(0)?,
+        listens: row.get(1)?,
+        listened_seconds: row.get(2)?,
+    }))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn streaks(conn: &Connection, since: u64) -> crate::Result<(Option<Streak>, u64)> {
+    let mut statement = conn.prepare(
+        "SELECT DISTINCT date(timestamp, 'unixepoch', 'localtime') AS day,
+            CAST(julianday(date(timestamp, 'unixepoch', 'localtime')) AS INTEGER)
+        FROM listens
+        WHERE timestamp >= ?1
+        ORDER BY day")?;
+
+    let days = statement.query_map(params![since], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?.collect::<Result<Vec<_>, _>>()?;
+
+    // Find runs of consecutive days.
+    let mut longest: Option<Streak> = None;
+    let mut start = 0;
+    for index in 0..days.len() {
+        let next = days.get(index + 1).map(|(_, day)| *day);
+        if next == Some(days[index].1 + 1) {
+            continue;
+        }
+
+        let length = (index - start + 1) as u64;
+        let longer = match &longest {
+            Some(streak) => length > streak.days,
+            None => true,
+        };
+
+        if longer {
+            longest = Some(Streak {
+                days: length,
+                start: days[start].0.clone(),
+                end: days[index].0.clone(),
+            });
+        }
+
+        start = index + 1;
+    }
+
+    // The current streak may end today or yesterday.
+    let today: i64 = conn.query_row(
+        "SELECT CAST(julianday(date('now', 'localtime')) AS INTEGER)",
+        [], |row| row.get(0))?;
+
+    let mut current = 0;
+    if let Some((_, last)) = days.last() {
+        if today - last <= 1 {
+            current = 1;
+            for pair in days.windows(2).rev() {
+                match pair[1].1 - pair[0].1 {
+                    1 => current += 1,
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    Ok((longest, current))
+}
+
+fn hours(conn: &Connection, since: u64) -> crate::Result<[u64; 24]> {
+    let mut statement = conn.prepare(
+        "SELECT CAST(strftime('%H', timestamp, 'unixepoch', 'localtime') AS INTEGER), SUM(listened)
+        FROM listens
+        WHERE timestamp >= ?1
+        GROUP BY 1")?;
+
+    let mut hours = [0; 24];
+    let mut rows = statement.query(params![since])?;
+    while let Some(row) = rows.next()? {
+        let hour: usize = row.get(0)?;
+        hours[hour] = row.get(1)?;
+    }
+
+    Ok(hours)
+}
+
+pub fn print(since: Option<Duration>, limit: usize, json: bool) -> crate::Result<()> {
+    let stats = stats(since, limit)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    println!("Listens: {}", stats.listens);
+    println!("Listening time: {}", format_seconds(stats.listened_seconds));
+
+    let tables = [
+        ("Top tracks", &stats.tracks),
+        ("Top artists", &stats.artists),
+        ("Top albums", &stats.albums),
+        ("Top origins", &stats.origins),
+    ];
+
+    for (heading, counts) in tables {
+        if counts.is_empty() {
+            continue;
+        }
+
+        println!("\n{}", heading);
+        for (index, count) in counts.iter().enumerate() {
+            let listened = format_seconds(count.listened_seconds);
+            println!("{:>4}. {} ({} listens, {})", index + 1, count.name, count.listens, listened);
+        }
+    }
+
+    println!("\nStreaks");
+    if let Some(streak) = &stats.longest_streak {
+        println!("  Longest: {} days ({} to {})", streak.days, streak.start, streak.end);
+    }
+
+    println!("  Current: {} days", stats.current_streak);
+
+    // Scale bars to the busiest hour.
+    println!("\nHours");
+    let maximum = stats.hours.iter().copied().max().unwrap_or(0).max(1);
+    for (hour, seconds) in stats.hours.iter().enumerate() {
+        let bar = "█".repeat((seconds * 40 / maximum) as usize);
+        println!("  {:02} {:<40} {}", hour, bar, format_seconds(*seconds));
+    }
+
+    Ok(())
+}
+
+fn format_seconds(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    match minutes / 60 {
+        0 => format!("{}m", minutes),
+        hours => format!("{}h {:02}m", hours, minutes % 60),
+    }
+}
@@ -0,0 +1,30 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty temporary directory for a test, removed when dropped.
+/// Each process and test has its own directory so that tests can run in parallel.
+pub struct TempDirectory(PathBuf);
+
+impl TempDirectory {
+    pub fn new(test: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("pmu-test-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}